@@ -31,7 +31,7 @@ let
       buildInputs = [ libtorch ] ++
         lib.optional stdenv.isDarwin darwin.Security;
 
-      features = [];
+      features = [ "extension-module" ];
 
       installPhase = let
         sitePackages = python3Packages.python.sitePackages;
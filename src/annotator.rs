@@ -1,15 +1,54 @@
+use std::collections::HashMap;
 use std::ops::Deref;
-use std::sync::Arc;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, RwLock};
 
+use conllu::graph::Sentence;
+use conllu::token::Token;
 use pyo3::exceptions;
 use pyo3::prelude::*;
-use sticker2::input::Tokenize;
-use sticker2::tagger::Tagger;
+use pyo3::types::PyDict;
+use sticker2::config::Config;
+use sticker2::input::{SentenceWithPieces, Tokenize};
 use tch::Device;
+use tempfile::TempDir;
 
-use crate::io::Model;
+use crate::archive;
+use crate::cache::default_cache_path;
+use crate::evaluation;
+use crate::io::{load_piece_vocab, load_tokenizer, max_pieces, read_sentences, Model};
+use crate::layer::{merge_layers, Layer, LayerKind};
+use crate::length::{mark_skipped, stitch, stitch_candidates, LengthPolicy};
+use crate::lexicon::{lexicons_from_dicts, Lexicon};
+use crate::metadata::Metadata;
+use crate::registry;
+use crate::spacy::register_spacy_component;
+use crate::tagger::Tagger;
+use crate::tree::{DependencyLabels, TreeDecoder};
 use crate::{PyConfig, PySentence};
 
+/// Handling of errors during annotation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ErrorHandling {
+    Collect,
+    Raise,
+    Skip,
+}
+
+impl ErrorHandling {
+    fn from_name(name: &str) -> PyResult<Self> {
+        match name {
+            "collect" => Ok(ErrorHandling::Collect),
+            "raise" => Ok(ErrorHandling::Raise),
+            "skip" => Ok(ErrorHandling::Skip),
+            _ => Err(exceptions::PyValueError::new_err(format!(
+                "unknown error handling '{}', expected one of: collect, raise, skip",
+                name
+            ))),
+        }
+    }
+}
+
 /// A wrapper of `Tagger` that is `Send + Sync`.
 ///
 /// Tensors are not thread-safe in the general case, but
@@ -31,32 +70,310 @@ impl Deref for TaggerWrap {
     }
 }
 
+/// The word piece tokenizer of a model.
+struct PieceTokenizer {
+    tokenizer: Box<dyn Tokenize>,
+
+    /// The word pieces, if the tokenizer has a plain-text vocabulary.
+    vocab: Option<Vec<String>>,
+
+    /// Number of pieces that the tokenizer adds to every sentence.
+    special_pieces: usize,
+}
+
+impl PieceTokenizer {
+    fn load(config: &Config) -> PyResult<Self> {
+        let tokenizer = load_tokenizer(config).map_err(|err| {
+            exceptions::PyIOError::new_err(format!("cannot load tokenizer: {}", err.to_string()))
+        })?;
+
+        let vocab = load_piece_vocab(config).map_err(|err| {
+            exceptions::PyIOError::new_err(format!(
+                "cannot load word piece vocabulary: {}",
+                err.to_string()
+            ))
+        })?;
+
+        // The pieces of an empty sentence are the special pieces that
+        // the tokenizer adds to every sentence.
+        let special_pieces = tokenizer.tokenize(Sentence::new()).pieces.len();
+
+        Ok(PieceTokenizer {
+            tokenizer,
+            vocab,
+            special_pieces,
+        })
+    }
+
+    fn tokenize(&self, sentence: Sentence) -> SentenceWithPieces {
+        self.tokenizer.tokenize(sentence)
+    }
+}
+
+/// The files of a model and how they are loaded.
+struct ModelSource {
+    config: Config,
+    metadata: Metadata,
+    parameter_cache: Option<String>,
+
+    /// The tokenizer, loaded on first use.
+    tokenizer: Mutex<Option<Arc<PieceTokenizer>>>,
+
+    /// Lock that is held while the model is loaded on first use.
+    loading: Mutex<()>,
+
+    /// Directory of an extracted model archive, removed when the model
+    /// and the source are dropped.
+    archive_dir: Mutex<Option<TempDir>>,
+}
+
+impl ModelSource {
+    fn new(
+        config: &PyConfig,
+        cache_parameters: bool,
+        parameter_cache: Option<String>,
+    ) -> PyResult<Self> {
+        let parameter_cache = match (cache_parameters, parameter_cache) {
+            (false, _) => None,
+            (true, Some(parameter_cache)) => Some(parameter_cache),
+            (true, None) => Some(default_cache_path(&config.as_ref().model.parameters)),
+        };
+
+        Ok(ModelSource {
+            config: config.as_ref().clone(),
+            metadata: config.metadata().clone(),
+            parameter_cache,
+            tokenizer: Mutex::new(None),
+            loading: Mutex::new(()),
+            archive_dir: Mutex::new(None),
+        })
+    }
+
+    /// Get the layers that the model annotates.
+    fn layers(&self) -> Vec<Layer> {
+        Layer::from_encoders(&self.config.labeler.encoders)
+    }
+
+    /// Keep the directory of an extracted model archive for the
+    /// lifetime of the source.
+    fn set_archive_dir(&self, archive_dir: TempDir) {
+        *self.archive_dir.lock().expect("Archive lock is poisoned") = Some(archive_dir);
+    }
+
+    /// Get the tokenizer, loading it if necessary.
+    ///
+    /// The tokenizer is loaded without loading the model.
+    fn tokenizer(&self) -> PyResult<Arc<PieceTokenizer>> {
+        let mut tokenizer = self.tokenizer.lock().expect("Tokenizer lock is poisoned");
+
+        if let Some(tokenizer) = &*tokenizer {
+            return Ok(tokenizer.clone());
+        }
+
+        let loaded = Arc::new(PieceTokenizer::load(&self.config)?);
+        *tokenizer = Some(loaded.clone());
+
+        Ok(loaded)
+    }
+
+    /// Load the model, verifying its files against the checksums in
+    /// the metadata.
+    fn load(self: &Arc<Self>) -> PyResult<LoadedModel> {
+        self.metadata
+            .checksums
+            .verify(&self.config)
+            .map_err(|err| {
+                exceptions::PyValueError::new_err(format!(
+                    "cannot verify model files: {}",
+                    err.to_string()
+                ))
+            })?;
+
+        let model = self.load_model()?;
+        let tokenizer = self.tokenizer()?;
+
+        let max_pieces = max_pieces(&self.config).map_err(|err| {
+            exceptions::PyIOError::new_err(format!(
+                "cannot determine maximum sentence length: {}",
+                err.to_string()
+            ))
+        })?;
+
+        let dependency_labels = DependencyLabels::from_encoders(&model.encoders)?;
+
+        let tagger = Tagger::new(Device::Cpu, model.model, &model.encoders)?;
+
+        Ok(LoadedModel {
+            dependency_labels,
+            max_pieces,
+            _source: self.clone(),
+            tagger: TaggerWrap(tagger),
+            tokenizer,
+        })
+    }
+
+    /// Load the sticker2 model, reading its parameters from the
+    /// parameter cache if a cache is used.
+    fn load_model(&self) -> PyResult<Model> {
+        let model = match &self.parameter_cache {
+            Some(cache) => Model::load_cached(&self.config, cache),
+            None => Model::load(&self.config, Device::Cpu),
+        };
+
+        model.map_err(|err| {
+            exceptions::PyIOError::new_err(format!(
+                "cannot load sticker2 model: {}",
+                err.to_string()
+            ))
+        })
+    }
+}
+
+/// A loaded model.
+///
+/// In-flight calls hold a reference to the model, so that it is only
+/// dropped when the annotator unloads or replaces the model and all
+/// calls that use it have finished.
+struct LoadedModel {
+    dependency_labels: Option<DependencyLabels>,
+    max_pieces: Option<usize>,
+
+    /// The source of the model, which keeps the directory of an
+    /// extracted model archive until the model is dropped.
+    _source: Arc<ModelSource>,
+    tagger: TaggerWrap,
+    tokenizer: Arc<PieceTokenizer>,
+}
+
+/// The model of an annotator.
+struct ModelState {
+    /// The model, `None` if it is not loaded.
+    loaded: Option<Arc<LoadedModel>>,
+    source: Arc<ModelSource>,
+}
+
+/// Annotator(config, lexicons=None, unknown=None, tree_decoder=None, long_sentences="error", cache_parameters=False, parameter_cache=None, lazy=False)
+/// --
+///
+/// Annotator for a sticker2 model.
+///
+/// Predicted labels can be restricted using lexicons. `lexicons` maps
+/// layer names (the names of the model's encoders, except dependency
+/// encoders) to dictionaries from word forms to lists of admissible
+/// labels. A word receives the admissible label to which the model
+/// assigns the highest probability. If the model does not know any of
+/// the admissible labels of a word, the first admissible label is
+/// used. A word that is not in the lexicon is looked up in lowercase.
+/// `unknown` maps layer names to the admissible labels of words that
+/// are not in the lexicon of that layer. Without such a list, the
+/// predictions for unknown words are not restricted.
+///
+/// The model predicts the head of every token independently, which can
+/// result in dependency graphs with cycles or multiple roots. If
+/// `tree_decoder` is `mst` (Chu-Liu-Edmonds) or `eisner` (projective),
+/// the most probable single-rooted tree under the head distributions
+/// of the model's dependency encoder is decoded instead. A model
+/// without a dependency encoder is not affected by `tree_decoder`.
+///
+/// Models with learned position embeddings have a maximum number of
+/// word pieces per sentence. `long_sentences` sets the policy for
+/// sentences that exceed this maximum: `error` raises a `ValueError`,
+/// `window` annotates overlapping windows and uses the prediction for
+/// each token from the window with the most context, `truncate` only
+/// annotates the tokens that fit with a warning, and `skip` leaves the
+/// sentence unannotated and adds `Sticker2Skipped=TooLong` to the
+/// `misc` column of the first token.
+///
+/// If `cache_parameters` is true, the model parameters are stored in a
+/// raw cache file at `parameter_cache` (default: the parameter file
+/// with the suffix `.cache`) when the model is first loaded. Later
+/// loads copy the parameters from the cache, which avoids decoding the
+/// parameter file. The cache records the size and checksum of the
+/// parameter file and the checksum of its own data, a cache that does
+/// not match is replaced. If the cache cannot be written, a warning is
+/// printed and the model is loaded without the cache. Every process
+/// has its own copy of the parameters. To share the parameters between
+/// worker processes, construct the annotator (with `lazy=False`) before
+/// forking: the parameters are never modified, so their pages remain
+/// shared.
+///
+/// If `lazy` is true, the model is loaded when it is first used rather
+/// than by the constructor. The model is then loaded without holding
+/// the GIL, so that other threads can run in the meantime. `unload`
+/// frees the model and `reload` loads it again from its files. An
+/// annotator can be used as a context manager, which loads the model
+/// on entry and unloads it on exit. The model is also unloaded on exit
+/// when the annotator is not lazy, it is loaded again when the
+/// annotator is used afterwards. `tokenize` does not load the model.
+///
+/// `swap` replaces the model of an annotator by another model without
+/// interrupting annotation.
 #[pyclass(name=Annotator)]
 pub struct PyAnnotator {
-    tagger: Arc<TaggerWrap>,
-    tokenizer: Arc<Box<dyn Tokenize>>,
+    length_policy: LengthPolicy,
+    lexicons: Vec<Lexicon>,
+    model: RwLock<ModelState>,
+    tree_decoder: Option<TreeDecoder>,
 }
 
 #[pymethods]
 impl PyAnnotator {
     #[new]
-    fn __new__(config: &PyConfig) -> PyResult<Self> {
-        let model = Model::load(&config.as_ref(), Device::Cpu).map_err(|err| {
-            exceptions::PyIOError::new_err(format!(
-                "cannot load sticker2 model: {}",
-                err.to_string()
-            ))
-        })?;
+    #[args(
+        lexicons = "None",
+        unknown = "None",
+        tree_decoder = "None",
+        long_sentences = "\"error\"",
+        cache_parameters = "false",
+        parameter_cache = "None",
+        lazy = "false"
+    )]
+    fn __new__(
+        config: &PyConfig,
+        lexicons: Option<HashMap<String, HashMap<String, Vec<String>>>>,
+        unknown: Option<HashMap<String, Vec<String>>>,
+        tree_decoder: Option<&str>,
+        long_sentences: &str,
+        cache_parameters: bool,
+        parameter_cache: Option<String>,
+        lazy: bool,
+    ) -> PyResult<Self> {
+        let lexicons = lexicons_from_dicts(
+            lexicons.unwrap_or_default(),
+            unknown.unwrap_or_default(),
+            &Layer::from_encoders(&config.as_ref().labeler.encoders),
+        )?;
+        let tree_decoder = tree_decoder.map(TreeDecoder::from_name).transpose()?;
+        let length_policy = LengthPolicy::from_name(long_sentences)?;
 
-        let tagger = Tagger::new(Device::Cpu, model.model, model.encoders);
+        let source = Arc::new(ModelSource::new(config, cache_parameters, parameter_cache)?);
+        let loaded = if lazy {
+            None
+        } else {
+            Some(Arc::new(source.load()?))
+        };
 
         Ok(PyAnnotator {
-            tagger: Arc::new(TaggerWrap(tagger)),
-            tokenizer: Arc::new(model.tokenizer),
+            length_policy,
+            lexicons,
+            model: RwLock::new(ModelState { loaded, source }),
+            tree_decoder,
         })
     }
 
-    /// annotate_sentence(sentence)
+    /// Load the model and return the annotator.
+    fn __enter__(slf: PyRef<Self>) -> PyResult<PyRef<Self>> {
+        slf.model()?;
+        Ok(slf)
+    }
+
+    /// Unload the model.
+    fn __exit__(&self, _exc_type: &PyAny, _exc_value: &PyAny, _traceback: &PyAny) -> bool {
+        self.unload();
+        false
+    }
+
+    /// annotate_sentence(sentence, only=None, skip=None, overwrite=True)
     /// --
     ///
     /// Annotate a sentence. The annotated sentences are returned.
@@ -65,33 +382,880 @@ impl PyAnnotator {
     /// ----------
     /// sentence : Sentence
     ///     Sentence object to annotate.
-    fn annotate_sentence(&self, sentence: PyRef<PySentence>) -> PyResult<PySentence> {
-        self.annotate_sentences(vec![sentence])
-            .map(|mut s| s.pop().expect("Tagging returned empty Vec"))
+    /// only : list
+    ///     See `annotate_sentences`.
+    /// skip : list
+    ///     See `annotate_sentences`.
+    /// overwrite : bool
+    ///     See `annotate_sentences`.
+    #[args(only = "None", skip = "None", overwrite = "true")]
+    fn annotate_sentence(
+        &self,
+        sentence: PyRef<PySentence>,
+        only: Option<Vec<String>>,
+        skip: Option<Vec<String>>,
+        overwrite: bool,
+    ) -> PyResult<PySentence> {
+        let layers = self.select_layers(only, skip)?;
+        let multiword_tokens = sentence.multiword_tokens().clone();
+
+        let annotated = self
+            .annotate(&[sentence.inner().clone()], &layers, overwrite)?
+            .pop()
+            .expect("Tagging returned empty Vec");
+
+        Ok(PySentence::new(annotated, multiword_tokens))
     }
 
-    /// annotate_sentences(sentences)
+    /// annotate_sentences(sentences, only=None, skip=None, overwrite=True, errors="raise")
     /// --
     ///
     /// Annotate a list of sentences. The annotated sentences are returned.
     ///
+    /// By default, all layers that the model predicts are replaced by
+    /// the predictions. The `only` and `skip` arguments restrict the
+    /// layers that are updated. Layers are named after the encoders of
+    /// the model. The layer of a dependency encoder covers both the
+    /// head and the relation of a token.
+    ///
+    /// By default, an error in any sentence raises an exception. With
+    /// `errors="skip"` or `errors="collect"`, a failing batch is
+    /// retried sentence by sentence and sentences that fail are
+    /// returned unannotated. Internal errors of the model are raised as
+    /// `RuntimeError` and isolated in the same way. With `errors="collect"`, a dictionary is
+    /// returned with the keys `sentences` (the sentences) and `errors`
+    /// (a list of `(index, exception)` tuples of the failed sentences).
+    ///
     /// Parameters
     /// ----------
     /// sentences : list
     ///     List of Sentence objects to annotate.
-    fn annotate_sentences(&self, sentences: Vec<PyRef<PySentence>>) -> PyResult<Vec<PySentence>> {
-        let mut sentences_with_pieces = sentences
+    /// only : list
+    ///     Only update these layers.
+    /// skip : list
+    ///     Do not update these layers.
+    /// overwrite : bool
+    ///     If false, only tokens that are not annotated in a layer
+    ///     receive a predicted annotation for that layer. A
+    ///     `ValueError` is raised when the predicted dependency
+    ///     relations and the existing relations do not form a tree.
+    /// errors : str
+    ///     Error handling: `raise`, `skip`, or `collect`.
+    #[args(only = "None", skip = "None", overwrite = "true", errors = "\"raise\"")]
+    fn annotate_sentences(
+        &self,
+        py: Python,
+        sentences: Vec<PyRef<PySentence>>,
+        only: Option<Vec<String>>,
+        skip: Option<Vec<String>>,
+        overwrite: bool,
+        errors: &str,
+    ) -> PyResult<PyObject> {
+        let errors = ErrorHandling::from_name(errors)?;
+        let layers = self.select_layers(only, skip)?;
+
+        let multiword_tokens = sentences
+            .iter()
+            .map(|sent| sent.multiword_tokens().clone())
+            .collect::<Vec<_>>();
+
+        let originals = sentences
+            .into_iter()
+            .map(|sent| sent.inner().clone())
+            .collect::<Vec<_>>();
+
+        let (annotated, failures) = annotate_isolated(originals, errors, |sentences| {
+            self.annotate(sentences, &layers, overwrite)
+        })?;
+        let failures = failures
+            .into_iter()
+            .map(|(idx, err)| (idx, err.to_object(py)))
+            .collect::<Vec<_>>();
+
+        let annotated = annotated
+            .into_iter()
+            .zip(multiword_tokens)
+            .map(|(sentence, multiword_tokens)| PySentence::new(sentence, multiword_tokens))
+            .collect::<Vec<_>>();
+
+        if errors == ErrorHandling::Collect {
+            let dict = PyDict::new(py);
+            dict.set_item("sentences", annotated.into_py(py))?;
+            dict.set_item("errors", failures)?;
+            Ok(dict.into())
+        } else {
+            Ok(annotated.into_py(py))
+        }
+    }
+
+    /// as_spacy_component(name="sticker2")
+    /// --
+    ///
+    /// Register the annotator as a spaCy pipeline component. The name
+    /// of the component is returned, so that the component can be
+    /// added to a pipeline using `nlp.add_pipe(name)`.
+    ///
+    /// Parameters
+    /// ----------
+    /// name : str
+    ///     Name to register the component under.
+    #[args(name = "\"sticker2\"")]
+    fn as_spacy_component(slf: PyRef<Self>, name: &str) -> PyResult<String> {
+        let py = slf.py();
+        register_spacy_component(py, slf.into(), name)?;
+        Ok(name.to_owned())
+    }
+
+    /// accuracy_drift(reference, path, batch_size=32)
+    /// --
+    ///
+    /// Compare the accuracy of the annotator to a reference annotator
+    /// on a gold-standard CoNLL-U file, for example to measure the
+    /// accuracy lost by a smaller or reduced-precision model. The
+    /// layers that both annotators predict are compared. For every
+    /// layer, a dictionary is returned with the keys `accuracy`,
+    /// `reference_accuracy`, `drift` (the difference of the
+    /// accuracies), and `disagreement` (the fraction of tokens for
+    /// which the annotators predict different labels).
+    ///
+    /// Parameters
+    /// ----------
+    /// reference : Annotator
+    ///     The annotator to compare to.
+    /// path : str
+    ///     Path of the gold-standard CoNLL-U file.
+    /// batch_size : int
+    ///     Number of sentences to annotate at a time.
+    #[args(batch_size = "32")]
+    fn accuracy_drift(
+        &self,
+        py: Python,
+        reference: PyRef<PyAnnotator>,
+        path: &str,
+        batch_size: usize,
+    ) -> PyResult<PyObject> {
+        let gold_sentences = read_gold_sentences(path)?;
+        let reference_sentences = reference.annotate_gold(&gold_sentences, batch_size)?;
+        let predicted_sentences = self.annotate_gold(&gold_sentences, batch_size)?;
+
+        let reference_layers = reference.layers();
+        let layers = self
+            .layers()
             .into_iter()
-            .map(|sent| self.tokenizer.tokenize(sent.inner().clone()))
+            .filter(|layer| reference_layers.contains(layer))
+            .collect::<Vec<_>>();
+
+        evaluation::accuracy_drift(
+            py,
+            &gold_sentences.iter().collect::<Vec<_>>(),
+            &reference_sentences.iter().collect::<Vec<_>>(),
+            &predicted_sentences.iter().collect::<Vec<_>>(),
+            &layers,
+        )
+    }
+
+    /// from_archive(path, **kwargs)
+    /// --
+    ///
+    /// Load an annotator from a model archive, as created by
+    /// `sticker2.package_model`. The archive is extracted to a
+    /// temporary directory, which is removed when the model is no
+    /// longer used, after the annotator is deleted or its model is
+    /// swapped. Keyword arguments are passed to the `Annotator`
+    /// constructor.
+    ///
+    /// Parameters
+    /// ----------
+    /// path : str
+    ///     Path of the model archive (tar or zip).
+    #[staticmethod]
+    #[args(kwargs = "**")]
+    fn from_archive(py: Python, path: &str, kwargs: Option<&PyDict>) -> PyResult<Py<PyAnnotator>> {
+        let (archive_dir, config_path) = archive::extract(path).map_err(|err| {
+            exceptions::PyIOError::new_err(format!(
+                "cannot extract model archive: {}",
+                err.to_string()
+            ))
+        })?;
+
+        let annotator = PyAnnotator::construct(py, &config_path, kwargs)?;
+        annotator.borrow().source().set_archive_dir(archive_dir);
+
+        Ok(annotator.into())
+    }
+
+    /// load(name, root=None, **kwargs)
+    /// --
+    ///
+    /// Load an annotator for a model in the registry. See
+    /// `sticker2.registry` for the layout of the registry. Keyword
+    /// arguments are passed to the `Annotator` constructor.
+    ///
+    /// Parameters
+    /// ----------
+    /// name : str
+    ///     Name of the model.
+    /// root : str
+    ///     Registry root. Default: the `STICKER2_MODELS` environment
+    ///     variable.
+    #[staticmethod]
+    #[args(root = "None", kwargs = "**")]
+    fn load(
+        py: Python,
+        name: &str,
+        root: Option<&str>,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<Py<PyAnnotator>> {
+        let config_path = registry::config_path(name, root)?;
+        Ok(PyAnnotator::construct(py, &config_path, kwargs)?.into())
+    }
+
+    /// evaluate(path, batch_size=32)
+    /// --
+    ///
+    /// Evaluate the annotator on a gold-standard CoNLL-U file. The
+    /// annotations of the gold-standard sentences are removed before
+    /// annotation. The layers of all encoders of the model are
+    /// evaluated, named after the encoders. See `sticker2.evaluate` for
+    /// a description of the returned evaluation.
+    ///
+    /// Parameters
+    /// ----------
+    /// path : str
+    ///     Path of the gold-standard CoNLL-U file.
+    /// batch_size : int
+    ///     Number of sentences to annotate at a time.
+    #[args(batch_size = "32")]
+    fn evaluate(&self, py: Python, path: &str, batch_size: usize) -> PyResult<PyObject> {
+        let gold_sentences = read_gold_sentences(path)?;
+        let predicted_sentences = self.annotate_gold(&gold_sentences, batch_size)?;
+
+        let gold_sentences = gold_sentences.iter().collect::<Vec<_>>();
+        let predicted_sentences = predicted_sentences.iter().collect::<Vec<_>>();
+        let layers = self.layers();
+
+        evaluation::evaluate(py, &gold_sentences, &predicted_sentences, &layers)
+    }
+
+    /// Whether the model is loaded.
+    #[getter]
+    fn get_loaded(&self) -> bool {
+        self.model
+            .read()
+            .expect("Model lock is poisoned")
+            .loaded
+            .is_some()
+    }
+
+    /// Get the metadata of the model.
+    ///
+    /// The metadata is a dictionary with the keys `name`, `version`,
+    /// `language`, `treebank`, `date`, and `checksums`, as read from
+    /// the `[metadata]` table of the configuration. Missing values are
+    /// `None`.
+    #[getter]
+    fn get_metadata<'py>(&self, py: Python<'py>) -> PyResult<&'py PyDict> {
+        self.model
+            .read()
+            .expect("Model lock is poisoned")
+            .source
+            .metadata
+            .to_dict(py)
+    }
+
+    /// reload()
+    /// --
+    ///
+    /// Load the model again from its files, for instance after the
+    /// files were updated. Calls that are in progress finish with the
+    /// previous model. If loading fails, the previous model is kept.
+    /// As in `swap`, the model is loaded without holding the GIL.
+    fn reload(&self, py: Python) -> PyResult<()> {
+        let source = self.source();
+        let loaded = Arc::new(py.allow_threads(|| source.load())?);
+
+        let mut state = self.model.write().expect("Model lock is poisoned");
+
+        // Do not replace a model that was swapped in the meantime.
+        if Arc::ptr_eq(&state.source, &source) {
+            state.loaded = Some(loaded);
+        }
+
+        Ok(())
+    }
+
+    /// swap(config, parameter_cache=None)
+    /// --
+    ///
+    /// Replace the model of the annotator by the model with the given
+    /// configuration. The new model is loaded without holding the GIL,
+    /// so that other threads can continue to annotate with the current
+    /// model. Once the new model is loaded, it replaces the current
+    /// model atomically. Calls that are in progress finish with the
+    /// previous model, later calls use the new model. If loading
+    /// fails, the current model is kept.
+    ///
+    /// The options of the annotator, such as lexicons, also apply to
+    /// the new model. A `ValueError` is raised and the current model
+    /// is kept if the new model does not annotate every layer of the
+    /// current model with the same name and kind, so that lexicons
+    /// and layers that are selected with `only` and `skip` remain
+    /// valid. If the annotator decodes trees, the new model must have
+    /// a dependency encoder.
+    ///
+    /// Parameters
+    /// ----------
+    /// config : Config
+    ///     Configuration of the new model.
+    /// parameter_cache : str
+    ///     Parameter cache of the new model, if the annotator caches
+    ///     parameters. Default: the parameter file with the suffix
+    ///     `.cache`.
+    #[args(parameter_cache = "None")]
+    fn swap(&self, py: Python, config: &PyConfig, parameter_cache: Option<String>) -> PyResult<()> {
+        let current = self.source();
+        let source = Arc::new(ModelSource::new(
+            config,
+            current.parameter_cache.is_some(),
+            parameter_cache,
+        )?);
+
+        check_swap_layers(
+            &current.layers(),
+            &source.layers(),
+            &self.lexicons,
+            self.tree_decoder,
+        )?;
+
+        let loaded = Arc::new(py.allow_threads(|| source.load())?);
+
+        *self.model.write().expect("Model lock is poisoned") = ModelState {
+            loaded: Some(loaded),
+            source,
+        };
+
+        Ok(())
+    }
+
+    /// tokenize(sentence)
+    /// --
+    ///
+    /// Split the tokens of a sentence into word pieces using the
+    /// tokenizer of the model. Returns a dictionary with the keys:
+    ///
+    /// * `pieces`: the word pieces. `None` if the model's tokenizer
+    ///   does not have a plain-text vocabulary.
+    /// * `piece_ids`: the word piece identifiers.
+    /// * `token_offsets`: for each token, the index of its first
+    ///   piece. The pieces of token `i` (1-based) start at
+    ///   `token_offsets[i - 1]`.
+    ///
+    /// The tokenizer is loaded without loading the model, so that
+    /// tokenization does not load the model of a lazy annotator.
+    ///
+    /// Parameters
+    /// ----------
+    /// sentence : Sentence
+    ///     Sentence to tokenize.
+    fn tokenize<'py>(&self, py: Python<'py>, sentence: PyRef<PySentence>) -> PyResult<&'py PyDict> {
+        let tokenizer = self.source().tokenizer()?;
+        let with_pieces = tokenizer.tokenize(sentence.inner().clone());
+        let piece_ids = with_pieces.pieces.iter().cloned().collect::<Vec<i64>>();
+
+        let pieces = match &tokenizer.vocab {
+            Some(vocab) => Some(
+                piece_ids
+                    .iter()
+                    .map(|&id| {
+                        vocab.get(id as usize).cloned().ok_or_else(|| {
+                            exceptions::PyValueError::new_err(format!(
+                                "word piece identifier not in vocabulary: {}",
+                                id
+                            ))
+                        })
+                    })
+                    .collect::<PyResult<Vec<_>>>()?,
+            ),
+            None => None,
+        };
+
+        let dict = PyDict::new(py);
+        dict.set_item("pieces", pieces)?;
+        dict.set_item("piece_ids", piece_ids)?;
+        dict.set_item("token_offsets", with_pieces.token_offsets)?;
+
+        Ok(dict)
+    }
+
+    /// unload()
+    /// --
+    ///
+    /// Unload the model to free its memory. The model is loaded again
+    /// when the annotator is used. Calls that are in progress finish
+    /// before the memory of the model is freed.
+    fn unload(&self) {
+        self.model
+            .write()
+            .expect("Model lock is poisoned")
+            .loaded
+            .take();
+    }
+}
+
+impl PyAnnotator {
+    /// Construct an annotator from a configuration file.
+    ///
+    /// The annotator is constructed through Python, so that keyword
+    /// arguments are handled by the constructor.
+    fn construct<'py>(
+        py: Python<'py>,
+        config_path: &str,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<&'py PyCell<PyAnnotator>> {
+        let config = Py::new(py, PyConfig::from_file(config_path)?)?;
+        Ok(py
+            .get_type::<PyAnnotator>()
+            .call((config,), kwargs)?
+            .downcast()?)
+    }
+
+    /// Annotate sentences, only updating the given layers.
+    fn annotate(
+        &self,
+        sentences: &[Sentence],
+        layers: &[Layer],
+        overwrite: bool,
+    ) -> PyResult<Vec<Sentence>> {
+        let predicted = self.tag_sentences(sentences.to_vec())?;
+
+        if !overwrite || layers.len() != self.layers().len() {
+            predicted
+                .into_iter()
+                .zip(sentences)
+                .map(|(predicted, original)| merge_layers(original, &predicted, layers, overwrite))
+                .collect()
+        } else {
+            Ok(predicted)
+        }
+    }
+
+    /// Get the model, loading it if necessary.
+    ///
+    /// The model is loaded without holding the GIL or the model lock,
+    /// so that other threads are not blocked while the model loads.
+    fn model(&self) -> PyResult<Arc<LoadedModel>> {
+        if let Some(model) = &self.model.read().expect("Model lock is poisoned").loaded {
+            return Ok(model.clone());
+        }
+
+        let source = self.source();
+        let state = &self.model;
+        Python::with_gil(|py| {
+            py.allow_threads(|| {
+                // Concurrent calls wait for the first call to load the model.
+                let _loading = source.loading.lock().expect("Loading lock is poisoned");
+
+                // Another thread may have loaded the model in the meantime.
+                if let Some(model) = &state.read().expect("Model lock is poisoned").loaded {
+                    return Ok(model.clone());
+                }
+
+                let loaded = Arc::new(source.load()?);
+
+                // Do not replace a model that was swapped in the meantime.
+                let mut state = state.write().expect("Model lock is poisoned");
+                if Arc::ptr_eq(&state.source, &source) {
+                    state.loaded = Some(loaded.clone());
+                }
+
+                Ok(loaded)
+            })
+        })
+    }
+
+    /// Get the layers of the current model.
+    fn layers(&self) -> Vec<Layer> {
+        self.source().layers()
+    }
+
+    /// Select the layers to annotate.
+    fn select_layers(
+        &self,
+        only: Option<Vec<String>>,
+        skip: Option<Vec<String>>,
+    ) -> PyResult<Vec<Layer>> {
+        let available = self.layers();
+
+        let mut layers = match only {
+            Some(only) => Layer::select(&available, &only)?,
+            None => available.clone(),
+        };
+
+        if let Some(skip) = skip {
+            let skip = Layer::select(&available, &skip)?;
+            layers.retain(|layer| !skip.contains(layer));
+        }
+
+        Ok(layers)
+    }
+
+    /// Annotate gold-standard sentences after removing their
+    /// annotations.
+    fn annotate_gold(
+        &self,
+        gold_sentences: &[Sentence],
+        batch_size: usize,
+    ) -> PyResult<Vec<Sentence>> {
+        if batch_size == 0 {
+            return Err(exceptions::PyValueError::new_err(
+                "batch size must be at least 1",
+            ));
+        }
+
+        let mut predicted_sentences = Vec::with_capacity(gold_sentences.len());
+        for batch in gold_sentences.chunks(batch_size) {
+            let unannotated = batch
+                .iter()
+                .map(|sentence| {
+                    (1..sentence.len())
+                        .map(|idx| Token::new(sentence[idx].token().unwrap().form()))
+                        .collect::<Sentence>()
+                })
+                .collect();
+            predicted_sentences.extend(self.tag_sentences(unannotated)?);
+        }
+
+        Ok(predicted_sentences)
+    }
+
+    /// Get the source of the current model.
+    fn source(&self) -> Arc<ModelSource> {
+        self.model
+            .read()
+            .expect("Model lock is poisoned")
+            .source
+            .clone()
+    }
+
+    /// Tag sentences.
+    pub(crate) fn tag_sentences(&self, sentences: Vec<Sentence>) -> PyResult<Vec<Sentence>> {
+        let model = self.model()?;
+
+        // Sentences that exceed the maximum length are tagged in windows,
+        // `None` marks sentences that are tagged as a whole.
+        let mut sentences_with_pieces = Vec::with_capacity(sentences.len());
+        let mut sentence_windows = Vec::with_capacity(sentences.len());
+        for sentence in &sentences {
+            let with_pieces = model.tokenizer.tokenize(sentence.clone());
+
+            match model.max_pieces {
+                Some(max_pieces) if with_pieces.pieces.len() > max_pieces => {
+                    let windows = self.length_policy.windows(
+                        &with_pieces,
+                        max_pieces,
+                        model.tokenizer.special_pieces,
+                    )?;
+                    for window in &windows {
+                        let tokens = window
+                            .clone()
+                            .map(|idx| sentence[idx].token().unwrap().clone())
+                            .collect::<Sentence>();
+                        sentences_with_pieces.push(model.tokenizer.tokenize(tokens));
+                    }
+                    sentence_windows.push(Some(windows));
+                }
+                _ => {
+                    sentences_with_pieces.push(with_pieces);
+                    sentence_windows.push(None);
+                }
+            }
+        }
+
+        let distributions = model
+            .tagger
+            .tag_sentences(&mut sentences_with_pieces, &self.lexicons)?;
+
+        // Head candidates are only needed when trees are decoded.
+        let dependency_labels = model
+            .dependency_labels
+            .as_ref()
+            .filter(|_| self.tree_decoder.is_some());
+
+        let mut tagged = sentences_with_pieces.into_iter().zip(distributions).map(
+            |(with_pieces, distributions)| {
+                let candidates = dependency_labels.map(|labels| {
+                    labels.candidates(
+                        &with_pieces.sentence,
+                        distributions[labels.encoder_name()].view(),
+                    )
+                });
+                (with_pieces.sentence, candidates)
+            },
+        );
+
+        let mut annotated = Vec::with_capacity(sentences.len());
+        for (sentence, windows) in sentences.into_iter().zip(sentence_windows) {
+            let (sentence, candidates) = match windows {
+                None => tagged.next().expect("Missing tagged sentence"),
+                Some(windows) if windows.is_empty() => {
+                    let mut sentence = sentence;
+                    mark_skipped(&mut sentence);
+                    annotated.push(sentence);
+                    continue;
+                }
+                Some(windows) => {
+                    let mut window_sentences = Vec::with_capacity(windows.len());
+                    let mut window_candidates = Vec::with_capacity(windows.len());
+                    for window in windows {
+                        let (tagged, candidates) = tagged.next().expect("Missing tagged window");
+                        window_sentences.push((window.clone(), tagged));
+                        window_candidates.push(candidates.map(|candidates| (window, candidates)));
+                    }
+
+                    let candidates = window_candidates
+                        .into_iter()
+                        .collect::<Option<Vec<_>>>()
+                        .map(|windows| stitch_candidates(&sentence, &windows));
+
+                    (stitch(&sentence, &window_sentences), candidates)
+                }
+            };
+
+            let sentence = match (self.tree_decoder, candidates) {
+                (Some(tree_decoder), Some(candidates)) => {
+                    tree_decoder.decode(&sentence, &candidates)
+                }
+                _ => sentence,
+            };
+
+            annotated.push(sentence);
+        }
+
+        Ok(annotated)
+    }
+}
+
+/// Check that a new model can replace the current model.
+///
+/// The new model must annotate the layers of the lexicons, a
+/// dependency layer if trees are decoded, and all layers of the
+/// current model, which may be selected by calls in progress.
+fn check_swap_layers(
+    current: &[Layer],
+    new: &[Layer],
+    lexicons: &[Lexicon],
+    tree_decoder: Option<TreeDecoder>,
+) -> PyResult<()> {
+    if let Some(lexicon) = lexicons
+        .iter()
+        .find(|lexicon| !new.contains(lexicon.layer()))
+    {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "the new model does not annotate layer '{}', which has a lexicon",
+            lexicon.layer().name()
+        )));
+    }
+
+    let has_dependency_layer = new
+        .iter()
+        .any(|layer| *layer.kind() == LayerKind::Dependency);
+    if tree_decoder.is_some() && !has_dependency_layer {
+        return Err(exceptions::PyValueError::new_err(
+            "the tree decoder requires a dependency encoder, the new model does not have one",
+        ));
+    }
+
+    if let Some(layer) = current.iter().find(|layer| !new.contains(layer)) {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "the new model does not annotate layer '{}' of the current model",
+            layer.name()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Read gold-standard sentences from a CoNLL-U file.
+fn read_gold_sentences(path: &str) -> PyResult<Vec<Sentence>> {
+    read_sentences(path).map_err(|err| {
+        exceptions::PyIOError::new_err(format!(
+            "cannot read gold-standard sentences: {}",
+            err.to_string()
+        ))
+    })
+}
+
+/// Annotate items, isolating the items that fail.
+///
+/// The items are first annotated as a batch. Unless errors are raised,
+/// a failing batch is retried item by item. Items that fail are
+/// returned unannotated, together with their indices and errors.
+/// Panics are converted to a `RuntimeError`, so that a panic does not
+/// abort the other items.
+fn annotate_isolated<T, F>(
+    items: Vec<T>,
+    errors: ErrorHandling,
+    annotate: F,
+) -> PyResult<(Vec<T>, Vec<(usize, PyErr)>)>
+where
+    T: Clone,
+    F: Fn(&[T]) -> PyResult<Vec<T>>,
+{
+    let err = match catch_panic(|| annotate(&items)) {
+        Ok(annotated) => return Ok((annotated, Vec::new())),
+        Err(err) => err,
+    };
+
+    if errors == ErrorHandling::Raise {
+        return Err(err);
+    }
+
+    let mut failures = Vec::new();
+    let annotated = items
+        .into_iter()
+        .enumerate()
+        .map(
+            |(idx, item)| match catch_panic(|| annotate(std::slice::from_ref(&item))) {
+                Ok(mut annotated) => annotated.pop().expect("Tagging returned empty Vec"),
+                Err(err) => {
+                    failures.push((idx, err));
+                    item
+                }
+            },
+        )
+        .collect();
+
+    Ok((annotated, failures))
+}
+
+/// Call a function, converting a panic into a `RuntimeError`.
+fn catch_panic<T>(f: impl FnOnce() -> PyResult<T>) -> PyResult<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown error".to_owned());
+
+        Err(exceptions::PyRuntimeError::new_err(format!(
+            "annotation panicked: {}",
+            message
+        )))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use conllu::graph::Sentence;
+    use conllu::token::Token;
+    use pyo3::exceptions;
+    use pyo3::prelude::*;
+
+    use super::{annotate_isolated, check_swap_layers, ErrorHandling, ModelSource};
+    use crate::layer::Layer;
+    use crate::lexicon::Lexicon;
+    use crate::tree::TreeDecoder;
+    use crate::PyConfig;
+
+    const CONFIG_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/model/sticker.conf");
+
+    /// Double positive numbers, fail on negative numbers and panic on 0.
+    fn double(items: &[i32]) -> PyResult<Vec<i32>> {
+        items
+            .iter()
+            .map(|&item| match item {
+                0 => panic!("cannot double 0"),
+                item if item < 0 => Err(exceptions::PyValueError::new_err("negative")),
+                item => Ok(item * 2),
+            })
+            .collect()
+    }
+
+    fn layers(names: &[&str]) -> Vec<Layer> {
+        let names = names
+            .iter()
+            .map(|&name| name.to_owned())
             .collect::<Vec<_>>();
+        Layer::select(&Layer::conllu(), &names).unwrap()
+    }
 
-        self.tagger
-            .tag_sentences(&mut sentences_with_pieces)
-            .map_err(|err| exceptions::PyRuntimeError::new_err(err.to_string()))?;
+    fn failed_indices(failures: &[(usize, PyErr)]) -> Vec<usize> {
+        failures.iter().map(|(idx, _)| *idx).collect()
+    }
+
+    #[test]
+    fn batch_without_errors() {
+        let (annotated, failures) =
+            annotate_isolated(vec![1, 2, 3], ErrorHandling::Collect, double).unwrap();
+        assert_eq!(annotated, vec![2, 4, 6]);
+        assert!(failures.is_empty());
+    }
+
+    #[test]
+    fn errors_are_raised() {
+        assert!(annotate_isolated(vec![1, -2, 3], ErrorHandling::Raise, double).is_err());
+        assert!(annotate_isolated(vec![1, 0, 3], ErrorHandling::Raise, double).is_err());
+    }
+
+    #[test]
+    fn failing_items_are_skipped() {
+        let (annotated, failures) =
+            annotate_isolated(vec![1, -2, 3], ErrorHandling::Skip, double).unwrap();
+        assert_eq!(annotated, vec![2, -2, 6]);
+        assert_eq!(failed_indices(&failures), vec![1]);
+    }
+
+    #[test]
+    fn panics_are_collected() {
+        let (annotated, failures) =
+            annotate_isolated(vec![0, 1, -2, 3], ErrorHandling::Collect, double).unwrap();
+        assert_eq!(annotated, vec![0, 2, -2, 6]);
+        assert_eq!(failed_indices(&failures), vec![0, 2]);
+    }
 
-        Ok(sentences_with_pieces
+    #[test]
+    fn tokenizer_is_loaded_without_model() {
+        // The parameters of the test model are not valid, so loading
+        // the model would fail.
+        let config = PyConfig::from_file(CONFIG_PATH).unwrap();
+        let source = ModelSource::new(&config, false, None).unwrap();
+        assert!(source.load_model().is_err());
+
+        let tokenizer = source.tokenizer().unwrap();
+        assert!(Arc::ptr_eq(&tokenizer, &source.tokenizer().unwrap()));
+        assert_eq!(tokenizer.special_pieces, 0);
+
+        let sentence = vec![Token::new("Hallo"), Token::new("Welt")]
             .into_iter()
-            .map(|with_pieces| with_pieces.sentence.into())
-            .collect())
+            .collect::<Sentence>();
+        let with_pieces = tokenizer.tokenize(sentence);
+        let vocab = tokenizer.vocab.as_ref().unwrap();
+        assert_eq!(with_pieces.token_offsets, vec![0, 1]);
+        assert_eq!(vocab[with_pieces.pieces[0] as usize], "Hallo");
+        assert_eq!(vocab[with_pieces.pieces[1] as usize], "[UNK]");
+    }
+
+    #[test]
+    fn swap_requires_layers_of_current_model() {
+        let current = layers(&["upos", "deprel"]);
+        let extended = layers(&["deprel", "upos", "lemma"]);
+        assert!(check_swap_layers(&current, &extended, &[], None).is_ok());
+        assert!(check_swap_layers(&current, &layers(&["upos"]), &[], None).is_err());
+    }
+
+    #[test]
+    fn swap_requires_lexicon_layers() {
+        let lexicon = Lexicon::new(layers(&["upos"]).remove(0), Default::default(), None).unwrap();
+        let result = check_swap_layers(&[], &layers(&["xpos"]), &[lexicon], None);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn swap_requires_dependency_layer_for_tree_decoder() {
+        let tree_decoder = Some(TreeDecoder::from_name("mst").unwrap());
+        assert!(check_swap_layers(&[], &layers(&["upos"]), &[], tree_decoder).is_err());
+        assert!(check_swap_layers(&[], &layers(&["deprel"]), &[], tree_decoder).is_ok());
     }
 }
@@ -0,0 +1,407 @@
+//! Model archives.
+//!
+//! A model archive is a tar or zip file that contains all the files of
+//! a model in a flat directory, with the paths in the configuration
+//! rewritten to the file names in the archive. The paths are rewritten
+//! in the configuration as it was read, so that all other settings are
+//! packaged unchanged. The archive manifest
+//! (`MANIFEST_NAME`) lists the configuration file and the other files
+//! of the model. The packaged configuration contains the checksums of
+//! the model files, which are verified when the model is loaded.
+//!
+//! Archives are not trusted: the files in the manifest must be plain
+//! file names and the configuration may only refer to these files.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sticker2::config::Config;
+use tempfile::TempDir;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::config::parse_config;
+use crate::metadata::{model_files, Checksums, Metadata};
+
+/// The name of the manifest in an archive.
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// The name of the configuration file in an archive.
+const CONFIG_NAME: &str = "sticker2.conf";
+
+const MANIFEST_VERSION: u32 = 1;
+
+/// Archive manifest.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Manifest {
+    /// Version of the archive format.
+    pub version: u32,
+
+    /// The configuration file.
+    pub config: String,
+
+    /// Other files of the model.
+    pub files: Vec<String>,
+}
+
+/// Archive format.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Format {
+    Tar,
+    Zip,
+}
+
+impl Format {
+    /// Guess the format of an archive from its file name.
+    fn from_path(path: &str) -> Format {
+        if path.ends_with(".zip") {
+            Format::Zip
+        } else {
+            Format::Tar
+        }
+    }
+
+    /// Detect the format of an archive from its contents.
+    fn detect(path: &str) -> Result<Format> {
+        let mut magic = [0u8; 4];
+        let mut f = File::open(path).context(format!("Cannot open model archive: {}", path))?;
+        match f.read_exact(&mut magic) {
+            Ok(()) if &magic == b"PK\x03\x04" => Ok(Format::Zip),
+            _ => Ok(Format::Tar),
+        }
+    }
+}
+
+/// Extract a model archive to a temporary directory.
+///
+/// Returns the directory and the path of the configuration file. The
+/// directory is removed when it is dropped.
+pub fn extract(path: &str) -> Result<(TempDir, String)> {
+    let dir = tempfile::Builder::new()
+        .prefix("sticker2-model")
+        .tempdir()
+        .context("Cannot create directory to extract model archive")?;
+
+    match Format::detect(path)? {
+        Format::Tar => tar::Archive::new(File::open(path)?)
+            .unpack(dir.path())
+            .context(format!("Cannot extract tar archive: {}", path))?,
+        Format::Zip => extract_zip(path, dir.path())?,
+    }
+
+    let manifest_path = dir.path().join(MANIFEST_NAME);
+    let manifest: Manifest = serde_json::from_reader(
+        File::open(&manifest_path).context(format!("Archive has no manifest: {}", path))?,
+    )
+    .context(format!("Cannot read archive manifest: {}", path))?;
+
+    if manifest.version != MANIFEST_VERSION {
+        bail!(
+            "Unsupported model archive version: {}, expected version {}",
+            manifest.version,
+            MANIFEST_VERSION
+        );
+    }
+
+    for file in manifest.files.iter().chain(Some(&manifest.config)) {
+        check_file_name(file)?;
+
+        if !dir.path().join(file).is_file() {
+            bail!("File in manifest is missing from archive: {}", file);
+        }
+    }
+
+    let config_path = dir.path().join(&manifest.config);
+    let config_path = config_path
+        .to_str()
+        .context("Path of extracted configuration is not valid Unicode")?
+        .to_owned();
+
+    // The configuration must only refer to files in the archive.
+    let (config, _, _) = parse_config(&fs::read_to_string(&config_path)?)
+        .context(format!("Cannot read configuration in archive: {}", path))?;
+    if let Some(file) = model_files(&config).iter().find(|file| {
+        !manifest
+            .files
+            .iter()
+            .any(|manifest_file| manifest_file == *file)
+    }) {
+        bail!("Configuration refers to file outside the archive: {}", file);
+    }
+
+    Ok((dir, config_path))
+}
+
+/// Check that a file name in a manifest refers to a file in the
+/// archive directory.
+fn check_file_name(file: &str) -> Result<()> {
+    let mut components = Path::new(file).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => bail!("Invalid file name in archive manifest: {}", file),
+    }
+}
+
+fn extract_zip(path: &str, dir: &Path) -> Result<()> {
+    let mut archive =
+        ZipArchive::new(File::open(path)?).context(format!("Cannot read zip archive: {}", path))?;
+
+    for idx in 0..archive.len() {
+        let mut file = archive.by_index(idx)?;
+        let target = dir.join(file.sanitized_name());
+
+        if file.is_dir() {
+            fs::create_dir_all(&target)?;
+        } else {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            io::copy(&mut file, &mut File::create(&target)?)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Package the files of a model into an archive.
+///
+/// The archive is a zip file if `out_path` has the extension `.zip`,
+/// otherwise it is a tar file. `config_toml` is the configuration as
+/// it was read, without metadata. The files are read from the paths in
+/// `config`. The metadata is stored in the packaged configuration,
+/// with the checksums of the packaged files.
+pub fn package(
+    config: &Config,
+    config_toml: &toml::Value,
+    metadata: &Metadata,
+    out_path: &str,
+) -> Result<()> {
+    let mut metadata = metadata.clone();
+    metadata.checksums = Checksums::compute(config)?;
+
+    let mut config_toml = config_toml.clone();
+
+    let mut names = HashSet::new();
+    let mut files = Vec::new();
+    for (path, keys) in model_files(config)
+        .iter()
+        .zip(&toml_path_keys(&config_toml)?)
+    {
+        let name = Path::new(path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .context(format!("Cannot get file name of: {}", path))?
+            .to_owned();
+
+        if !names.insert(name.clone()) {
+            bail!("Model has multiple files with the name: {}", name);
+        }
+
+        set_toml_path(&mut config_toml, keys, &name)?;
+        files.push((path.to_string(), name));
+    }
+
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        config: CONFIG_NAME.to_owned(),
+        files: files.iter().map(|(_, name)| name.clone()).collect(),
+    };
+
+    let config_data = config_to_toml(config_toml, &metadata)?;
+    let manifest_data = serde_json::to_vec_pretty(&manifest)?;
+
+    let f = File::create(out_path).context(format!("Cannot create archive: {}", out_path))?;
+    match Format::from_path(out_path) {
+        Format::Tar => {
+            let mut builder = tar::Builder::new(f);
+            append_tar_data(&mut builder, MANIFEST_NAME, &manifest_data)?;
+            append_tar_data(&mut builder, CONFIG_NAME, &config_data)?;
+            for (path, name) in &files {
+                builder
+                    .append_path_with_name(path, name)
+                    .context(format!("Cannot add file to archive: {}", path))?;
+            }
+            builder.finish()?;
+        }
+        Format::Zip => {
+            let mut writer = ZipWriter::new(f);
+            let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+
+            writer.start_file(MANIFEST_NAME, options)?;
+            io::Write::write_all(&mut writer, &manifest_data)?;
+            writer.start_file(CONFIG_NAME, options)?;
+            io::Write::write_all(&mut writer, &config_data)?;
+            for (path, name) in &files {
+                writer.start_file(name.as_str(), options)?;
+                io::copy(
+                    &mut File::open(path).context(format!("Cannot open file: {}", path))?,
+                    &mut writer,
+                )?;
+            }
+            writer.finish()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Serialize a configuration with its metadata.
+fn config_to_toml(mut config: toml::Value, metadata: &Metadata) -> Result<Vec<u8>> {
+    config
+        .as_table_mut()
+        .context("Configuration is not a table")?
+        .insert(
+            "metadata".to_owned(),
+            toml::Value::try_from(metadata).context("Cannot serialize metadata")?,
+        );
+
+    Ok(toml::to_vec(&config)?)
+}
+
+fn append_tar_data(builder: &mut tar::Builder<File>, name: &str, data: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+/// Get the keys of the file paths in a configuration.
+///
+/// The keys are in the same order as the files of `model_files`.
+fn toml_path_keys(config: &toml::Value) -> Result<[Vec<&str>; 4]> {
+    let tokenizer = config
+        .get("input")
+        .and_then(|input| input.get("tokenizer"))
+        .and_then(toml::Value::as_table)
+        .and_then(|tokenizer| tokenizer.keys().next())
+        .context("Configuration does not have a tokenizer")?;
+
+    Ok([
+        vec!["labeler", "labels"],
+        vec!["model", "parameters"],
+        vec!["model", "pretrain_config"],
+        vec!["input", "tokenizer", tokenizer.as_str(), "vocab"],
+    ])
+}
+
+/// Set the file path with the given keys in a configuration.
+fn set_toml_path(config: &mut toml::Value, keys: &[&str], path: &str) -> Result<()> {
+    let (key, table_keys) = keys.split_last().expect("Empty configuration key");
+
+    let mut table = config;
+    for &table_key in table_keys {
+        table = table
+            .get_mut(table_key)
+            .context(format!("Configuration does not have table: {}", table_key))?;
+    }
+
+    table
+        .as_table_mut()
+        .context(format!("Configuration does not have table for: {}", key))?
+        .insert((*key).to_owned(), toml::Value::String(path.to_owned()));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use super::{extract, package, Manifest, MANIFEST_NAME, MANIFEST_VERSION};
+    use crate::config::parse_config;
+
+    const CONFIG_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/model/sticker.conf");
+
+    fn package_and_extract(archive_name: &str) {
+        let (mut config, config_toml, metadata) =
+            parse_config(&fs::read_to_string(CONFIG_PATH).unwrap()).unwrap();
+        config.relativize_paths(CONFIG_PATH).unwrap();
+
+        let out_dir = tempfile::tempdir().unwrap();
+        let archive_path = out_dir.path().join(archive_name);
+        let archive_path = archive_path.to_str().unwrap();
+        package(&config, &config_toml, &metadata, archive_path).unwrap();
+
+        let (_dir, extracted_path) = extract(archive_path).unwrap();
+        let (mut extracted, extracted_toml, extracted_metadata) =
+            parse_config(&fs::read_to_string(&extracted_path).unwrap()).unwrap();
+
+        // Paths are rewritten to the file names in the archive.
+        assert_eq!(extracted.labeler.labels, "sticker.labels");
+        assert_eq!(extracted.model.parameters, "epoch-99");
+        assert_eq!(extracted.model.pretrain_config, "bert_config.json");
+        assert_eq!(
+            extracted_toml["input"]["tokenizer"]["bert"]["vocab"].as_str(),
+            Some("vocab.txt")
+        );
+
+        // Other settings are packaged unchanged.
+        assert_eq!(
+            extracted_toml["labeler"]["encoders"],
+            config_toml["labeler"]["encoders"]
+        );
+        assert_eq!(extracted_toml["model"], {
+            let mut model = config_toml["model"].clone();
+            model["parameters"] = "epoch-99".into();
+            model["pretrain_config"] = "bert_config.json".into();
+            model
+        });
+
+        // The metadata is retained and the checksums match the files.
+        assert_eq!(extracted_metadata.name, metadata.name);
+        assert!(extracted_metadata.checksums.parameters.is_some());
+        extracted.relativize_paths(&extracted_path).unwrap();
+        extracted_metadata.checksums.verify(&extracted).unwrap();
+    }
+
+    #[test]
+    fn package_tar() {
+        package_and_extract("model.tar");
+    }
+
+    #[test]
+    fn package_zip() {
+        package_and_extract("model.zip");
+    }
+
+    /// Construct a tar archive that only contains a manifest.
+    fn archive_with_manifest(dir: &tempfile::TempDir, config: &str, files: &[&str]) -> String {
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            config: config.to_owned(),
+            files: files.iter().map(|&file| file.to_owned()).collect(),
+        };
+        let manifest_path = dir.path().join(MANIFEST_NAME);
+        serde_json::to_writer(File::create(&manifest_path).unwrap(), &manifest).unwrap();
+
+        let archive_path = dir.path().join("model.tar");
+        let mut builder = tar::Builder::new(File::create(&archive_path).unwrap());
+        builder
+            .append_path_with_name(&manifest_path, MANIFEST_NAME)
+            .unwrap();
+        builder.finish().unwrap();
+
+        archive_path.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn manifest_paths_must_be_file_names() {
+        let dir = tempfile::tempdir().unwrap();
+        for (config, files) in &[
+            ("/etc/sticker2.conf", vec![]),
+            ("../sticker2.conf", vec![]),
+            ("sticker2.conf", vec!["model/epoch-99"]),
+            ("sticker2.conf", vec![".."]),
+        ] {
+            let archive_path = archive_with_manifest(&dir, config, files);
+            let err = extract(&archive_path).unwrap_err();
+            assert!(err.to_string().starts_with("Invalid file name"));
+        }
+    }
+}
@@ -0,0 +1,387 @@
+//! Parameter cache.
+//!
+//! Parameter files in libtorch format are archives that have to be
+//! decoded when a model is loaded. The parameter cache stores the raw
+//! data of every variable instead, which is copied into the variables
+//! when the model is loaded. The variables own their data: the
+//! parameters of processes that load the same model are not shared.
+//!
+//! The cache file consists of an 8-byte magic, the length of the header
+//! as a little-endian `u64`, a JSON header, and the variable data. The
+//! header describes the variables and stores the size and SHA-256
+//! checksum of the parameter file from which the cache was created, as
+//! well as the SHA-256 checksum of the variable data. The data of each
+//! variable is aligned to `ALIGNMENT` bytes, relative to the start of
+//! the data.
+
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::process;
+
+use anyhow::{bail, Context, Result};
+use memmap::Mmap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tch::nn::VarStore;
+use tch::{Device, Kind, Tensor};
+
+use crate::metadata::sha256;
+
+const ALIGNMENT: usize = 64;
+
+const MAGIC: &[u8; 8] = b"STK2PCAC";
+
+#[derive(Deserialize, Serialize)]
+struct Header {
+    parameters: ParameterFile,
+
+    /// SHA-256 checksum of the variable data.
+    checksum: String,
+
+    variables: Vec<Variable>,
+}
+
+/// The parameter file from which a cache was created.
+#[derive(Deserialize, Eq, PartialEq, Serialize)]
+struct ParameterFile {
+    size: u64,
+    checksum: String,
+}
+
+impl ParameterFile {
+    fn read(parameters: &str) -> Result<Self> {
+        let size = fs::metadata(parameters)
+            .context(format!("Cannot read metadata of: {}", parameters))?
+            .len();
+
+        Ok(ParameterFile {
+            size,
+            checksum: sha256(parameters)?,
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Variable {
+    name: String,
+    kind: String,
+    shape: Vec<i64>,
+    offset: usize,
+}
+
+/// Load the variables of a `VarStore` from a parameter cache.
+///
+/// The parameters are read from `cache`. If the cache does not exist
+/// or was not created from the current parameter file, the parameters
+/// are loaded from `parameters` and the cache is created. Failure to
+/// write the cache is not an error, the parameters are then loaded
+/// without a cache.
+pub fn load_cached_parameters(vs: &mut VarStore, parameters: &str, cache: &str) -> Result<()> {
+    if vs.device() != Device::Cpu {
+        bail!("Parameter caches are only supported on CPU");
+    }
+
+    let parameter_file = ParameterFile::read(parameters)?;
+
+    if let Some((header, mmap, data_offset)) = open_cache(cache, &parameter_file)? {
+        return copy_from_cache(vs, &header, &mmap[data_offset..])
+            .context(format!("Cannot load parameter cache: {}", cache));
+    }
+
+    vs.load(parameters)
+        .context("Cannot load model parameters")?;
+
+    if let Err(err) = write_cache(vs, cache, parameter_file) {
+        eprintln!("Warning: cannot write parameter cache {}: {:#}", cache, err);
+    }
+
+    Ok(())
+}
+
+/// Open a parameter cache.
+///
+/// Returns `None` when the cache does not exist, is not a valid cache,
+/// was created from a different parameter file, or when its data does
+/// not match its checksum.
+fn open_cache(
+    cache: &str,
+    parameter_file: &ParameterFile,
+) -> Result<Option<(Header, Mmap, usize)>> {
+    let f = match File::open(cache) {
+        Ok(f) => f,
+        Err(_) => return Ok(None),
+    };
+
+    let mmap = unsafe { Mmap::map(&f) }
+        .context(format!("Cannot memory-map parameter cache: {}", cache))?;
+
+    let (header, data_offset) = match read_header(&mmap) {
+        Ok(header) => header,
+        Err(_) => return Ok(None),
+    };
+
+    if header.parameters != *parameter_file {
+        return Ok(None);
+    }
+
+    if format!("{:x}", Sha256::digest(&mmap[data_offset..])) != header.checksum {
+        return Ok(None);
+    }
+
+    Ok(Some((header, mmap, data_offset)))
+}
+
+/// Copy the variable data of a parameter cache into a `VarStore`.
+fn copy_from_cache(vs: &mut VarStore, header: &Header, data: &[u8]) -> Result<()> {
+    let mut variables = vs.variables();
+    if header.variables.len() != variables.len() {
+        bail!(
+            "Parameter cache has {} variables, model has {} variables",
+            header.variables.len(),
+            variables.len()
+        );
+    }
+
+    for cached in &header.variables {
+        let var = variables.get_mut(&cached.name).context(format!(
+            "Unknown variable in parameter cache: {}",
+            cached.name
+        ))?;
+
+        let kind = kind_from_name(&cached.kind)?;
+        if var.kind() != kind {
+            bail!(
+                "Type of variable {} in parameter cache is {:?}, expected {:?}",
+                cached.name,
+                kind,
+                var.kind()
+            );
+        }
+
+        if var.size() != cached.shape {
+            bail!(
+                "Shape of variable {} in parameter cache is {:?}, expected {:?}",
+                cached.name,
+                cached.shape,
+                var.size()
+            );
+        }
+
+        let start = cached.offset;
+        let end = start + n_bytes(kind, &cached.shape)?;
+        if end > data.len() {
+            bail!("Data of variable {} is truncated", cached.name);
+        }
+
+        let tensor = Tensor::of_data_size(&data[start..end], &cached.shape, kind);
+        tch::no_grad(|| var.copy_(&tensor));
+    }
+
+    Ok(())
+}
+
+fn kind_from_name(name: &str) -> Result<Kind> {
+    match name {
+        "float16" => Ok(Kind::Half),
+        "float32" => Ok(Kind::Float),
+        "float64" => Ok(Kind::Double),
+        _ => bail!("Unsupported data type in parameter cache: {}", name),
+    }
+}
+
+fn kind_name(kind: Kind) -> Result<&'static str> {
+    match kind {
+        Kind::Half => Ok("float16"),
+        Kind::Float => Ok("float32"),
+        Kind::Double => Ok("float64"),
+        _ => bail!("Cannot store parameters of type {:?} in cache", kind),
+    }
+}
+
+/// Get the size of a tensor's data in bytes.
+fn n_bytes(kind: Kind, shape: &[i64]) -> Result<usize> {
+    let elem_size = match kind {
+        Kind::Half => 2,
+        Kind::Float => 4,
+        Kind::Double => 8,
+        _ => bail!("Unsupported data type: {:?}", kind),
+    };
+
+    Ok(shape.iter().product::<i64>() as usize * elem_size)
+}
+
+fn read_header(data: &[u8]) -> Result<(Header, usize)> {
+    if data.len() < 16 || &data[..8] != MAGIC {
+        bail!("Not a parameter cache");
+    }
+
+    let header_len = u64::from_le_bytes(data[8..16].try_into().unwrap()) as usize;
+    let data_offset = padded_len(16 + header_len);
+    if data.len() < data_offset {
+        bail!("Header is truncated");
+    }
+
+    let header = serde_json::from_slice(&data[16..16 + header_len])?;
+
+    Ok((header, data_offset))
+}
+
+fn padded_len(len: usize) -> usize {
+    (len + ALIGNMENT - 1) / ALIGNMENT * ALIGNMENT
+}
+
+/// Write the variables of a `VarStore` to a parameter cache.
+///
+/// The cache is written to a temporary file first, so that processes
+/// that load the same model concurrently never see a partial cache.
+fn write_cache(vs: &VarStore, cache: &str, parameter_file: ParameterFile) -> Result<()> {
+    let mut variables = vs
+        .variables()
+        .into_iter()
+        .map(|(name, tensor)| (name, tensor.contiguous()))
+        .collect::<Vec<_>>();
+    variables.sort_by(|(name1, _), (name2, _)| name1.cmp(name2));
+
+    let mut offset = 0;
+    let mut hasher = Sha256::new();
+    let mut header_variables = Vec::with_capacity(variables.len());
+    for (name, tensor) in &variables {
+        let shape = tensor.size();
+        let kind = tensor.kind();
+        header_variables.push(Variable {
+            name: name.clone(),
+            kind: kind_name(kind)?.to_owned(),
+            shape: shape.clone(),
+            offset,
+        });
+
+        let len = n_bytes(kind, &shape)?;
+        hasher.update(tensor_data(tensor, len));
+        write_padding(&mut hasher, len)?;
+
+        offset = padded_len(offset + len);
+    }
+
+    let header = serde_json::to_vec(&Header {
+        parameters: parameter_file,
+        checksum: format!("{:x}", hasher.finalize()),
+        variables: header_variables,
+    })?;
+
+    let tmp_path = format!("{}.{}.tmp", cache, process::id());
+    let f =
+        File::create(&tmp_path).context(format!("Cannot create parameter cache: {}", tmp_path))?;
+    let mut writer = BufWriter::new(f);
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&(header.len() as u64).to_le_bytes())?;
+    writer.write_all(&header)?;
+    write_padding(&mut writer, 16 + header.len())?;
+
+    for (_, tensor) in &variables {
+        let len = n_bytes(tensor.kind(), &tensor.size())?;
+        writer.write_all(tensor_data(tensor, len))?;
+        write_padding(&mut writer, len)?;
+    }
+
+    writer.flush()?;
+    drop(writer);
+
+    fs::rename(&tmp_path, cache)
+        .context(format!("Cannot move parameter cache into place: {}", cache))?;
+
+    Ok(())
+}
+
+/// Get the data of a contiguous CPU tensor of `len` bytes.
+fn tensor_data(tensor: &Tensor, len: usize) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(tensor.data_ptr() as *const u8, len) }
+}
+
+fn write_padding(writer: &mut impl Write, len: usize) -> Result<()> {
+    let padding = padded_len(len) - len;
+    writer.write_all(&vec![0u8; padding])?;
+    Ok(())
+}
+
+/// Get the default parameter cache path for a parameter file.
+pub fn default_cache_path(parameters: &str) -> String {
+    format!("{}.cache", parameters)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tch::nn::VarStore;
+    use tch::{Device, Tensor};
+    use tempfile::TempDir;
+
+    use super::load_cached_parameters;
+
+    /// Construct a `VarStore` with a single variable.
+    fn var_store(values: &[f32]) -> VarStore {
+        let vs = VarStore::new(Device::Cpu);
+        let var = vs.root().zeros("weight", &[values.len() as i64]);
+        tch::no_grad(|| var.copy_(&Tensor::of_slice(values)));
+        vs
+    }
+
+    fn weight(vs: &VarStore) -> Vec<f32> {
+        Vec::from(&vs.variables()["weight"])
+    }
+
+    #[test]
+    fn parameters_are_loaded_from_cache() {
+        let dir = TempDir::new().unwrap();
+        let parameters = dir.path().join("params").to_str().unwrap().to_owned();
+        let cache = dir.path().join("params.cache").to_str().unwrap().to_owned();
+        var_store(&[1., 2., 3.]).save(&parameters).unwrap();
+
+        let mut vs = var_store(&[0., 0., 0.]);
+        load_cached_parameters(&mut vs, &parameters, &cache).unwrap();
+        assert!(fs::metadata(&cache).is_ok());
+        assert_eq!(weight(&vs), vec![1., 2., 3.]);
+
+        // The cache is used when the parameter file is removed and
+        // replaced by an identical file.
+        fs::remove_file(&parameters).unwrap();
+        var_store(&[1., 2., 3.]).save(&parameters).unwrap();
+        let mut vs = var_store(&[0., 0., 0.]);
+        load_cached_parameters(&mut vs, &parameters, &cache).unwrap();
+        assert_eq!(weight(&vs), vec![1., 2., 3.]);
+    }
+
+    #[test]
+    fn cache_is_rebuilt_when_parameters_change() {
+        let dir = TempDir::new().unwrap();
+        let parameters = dir.path().join("params").to_str().unwrap().to_owned();
+        let cache = dir.path().join("params.cache").to_str().unwrap().to_owned();
+
+        var_store(&[1., 2., 3.]).save(&parameters).unwrap();
+        load_cached_parameters(&mut var_store(&[0., 0., 0.]), &parameters, &cache).unwrap();
+
+        var_store(&[4., 5., 6.]).save(&parameters).unwrap();
+        let mut vs = var_store(&[0., 0., 0.]);
+        load_cached_parameters(&mut vs, &parameters, &cache).unwrap();
+        assert_eq!(weight(&vs), vec![4., 5., 6.]);
+    }
+
+    #[test]
+    fn unwritable_cache_is_not_an_error() {
+        let dir = TempDir::new().unwrap();
+        let parameters = dir.path().join("params").to_str().unwrap().to_owned();
+        let cache = dir
+            .path()
+            .join("missing/params.cache")
+            .to_str()
+            .unwrap()
+            .to_owned();
+        var_store(&[1., 2., 3.]).save(&parameters).unwrap();
+
+        let mut vs = var_store(&[0., 0., 0.]);
+        load_cached_parameters(&mut vs, &parameters, &cache).unwrap();
+        assert_eq!(weight(&vs), vec![1., 2., 3.]);
+    }
+}
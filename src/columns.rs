@@ -0,0 +1,71 @@
+use conllu::graph::Sentence;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::sentence::features_to_string;
+
+/// Columnar representation of sentences.
+///
+/// Each column contains the values of one CoNLL-U field for all
+/// tokens, excluding the root.
+#[derive(Default)]
+pub struct Columns {
+    sentence: Vec<usize>,
+    id: Vec<usize>,
+    form: Vec<String>,
+    lemma: Vec<Option<String>>,
+    upos: Vec<Option<String>>,
+    xpos: Vec<Option<String>>,
+    feats: Vec<Option<String>>,
+    head: Vec<Option<usize>>,
+    deprel: Vec<Option<String>>,
+}
+
+impl Columns {
+    /// Add the tokens of a sentence to the columns.
+    pub fn push_sentence(&mut self, sentence_idx: usize, sentence: &Sentence) {
+        let dep_graph = sentence.dep_graph();
+
+        for token_idx in 1..sentence.len() {
+            let token = sentence[token_idx].token().unwrap();
+            let triple = dep_graph.head(token_idx);
+
+            self.sentence.push(sentence_idx);
+            self.id.push(token_idx);
+            self.form.push(token.form().to_owned());
+            self.lemma.push(token.lemma().map(ToOwned::to_owned));
+            self.upos.push(token.upos().map(ToOwned::to_owned));
+            self.xpos.push(token.xpos().map(ToOwned::to_owned));
+            self.feats.push(features_to_string(token));
+            self.head.push(triple.as_ref().map(|triple| triple.head()));
+            self.deprel.push(
+                triple
+                    .as_ref()
+                    .and_then(|triple| triple.relation().map(ToOwned::to_owned)),
+            );
+        }
+    }
+
+    /// Convert the columns to a Python dictionary.
+    ///
+    /// The `sentence` column is only added when `with_sentence` is
+    /// true.
+    pub fn into_dict(self, py: Python, with_sentence: bool) -> PyResult<PyObject> {
+        let dict = PyDict::new(py);
+
+        if with_sentence {
+            dict.set_item("sentence", self.sentence)?;
+        }
+
+        dict.set_item("id", self.id)?;
+        dict.set_item("form", self.form)?;
+        dict.set_item("lemma", self.lemma)?;
+        dict.set_item("upos", self.upos)?;
+        dict.set_item("xpos", self.xpos)?;
+        dict.set_item("feats", self.feats)?;
+        dict.set_item("head", self.head)?;
+        dict.set_item("deprel", self.deprel)?;
+
+        Ok(dict.to_object(py))
+    }
+}
@@ -1,13 +1,15 @@
 use std::cell::{Ref, RefCell};
-use std::fs::File;
-use std::io::BufReader;
+use std::fs;
 use std::rc::Rc;
 
+use anyhow::{Context, Result};
 use pyo3::class::basic::PyObjectProtocol;
 use pyo3::exceptions;
 use pyo3::prelude::*;
 
-use sticker2::config::{Config, TomlRead};
+use sticker2::config::Config;
+
+use crate::metadata::Metadata;
 
 /// Config(file)
 /// --
@@ -16,29 +18,38 @@ use sticker2::config::{Config, TomlRead};
 #[pyclass(name=Config,unsendable)]
 pub struct PyConfig {
     inner: Rc<RefCell<Config>>,
+    metadata: Metadata,
+
+    /// The configuration as it was read, without metadata.
+    toml: toml::Value,
 }
 
 impl PyConfig {
     pub fn as_ref(&self) -> Ref<Config> {
         self.inner.borrow()
     }
-}
 
-#[pymethods]
-impl PyConfig {
-    #[new]
-    fn __new__(path: &str) -> PyResult<Self> {
-        let reader = BufReader::new(File::open(path).map_err(|err| {
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Get the configuration as it was read, without metadata.
+    ///
+    /// The paths in this configuration are not relativized.
+    pub fn toml(&self) -> &toml::Value {
+        &self.toml
+    }
+
+    /// Read a configuration file.
+    pub fn from_file(path: &str) -> PyResult<Self> {
+        let data = fs::read_to_string(path).map_err(|err| {
             exceptions::PyIOError::new_err(format!(
                 "cannot read sticker configuration: {}",
                 err.to_string()
             ))
-        })?);
-        let mut config = Config::from_toml_read(reader).map_err(|err| {
-            exceptions::PyValueError::new_err(format!(
-                "cannot read parse configuration: {}",
-                err.to_string()
-            ))
+        })?;
+        let (mut config, toml, metadata) = parse_config(&data).map_err(|err| {
+            exceptions::PyValueError::new_err(format!("cannot parse configuration: {:#}", err))
         })?;
 
         config.relativize_paths(path).map_err(|err| {
@@ -47,8 +58,32 @@ impl PyConfig {
 
         Ok(PyConfig {
             inner: Rc::new(RefCell::new(config)),
+            metadata,
+            toml,
         })
     }
+}
+
+/// Parse a configuration, separating the model metadata.
+///
+/// Returns the configuration, its TOML representation without the
+/// metadata, and the metadata.
+pub(crate) fn parse_config(data: &str) -> Result<(Config, toml::Value, Metadata)> {
+    let mut value: toml::Value = toml::from_str(data)?;
+    let metadata = Metadata::take_from_config(&mut value)?;
+    let config = value
+        .clone()
+        .try_into()
+        .context("Cannot read sticker configuration")?;
+    Ok((config, value, metadata))
+}
+
+#[pymethods]
+impl PyConfig {
+    #[new]
+    fn __new__(path: &str) -> PyResult<Self> {
+        PyConfig::from_file(path)
+    }
 
     #[getter]
     fn get_labeler(&self) -> PyLabeler {
@@ -116,3 +151,44 @@ impl PyObjectProtocol for PyLabeler {
         Ok(format!("{:?}", self.config.borrow().labeler))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::parse_config;
+
+    #[test]
+    fn config_with_metadata() {
+        let (config, toml, metadata) =
+            parse_config(include_str!("../testdata/sticker.conf")).unwrap();
+
+        assert_eq!(config.labeler.labels, "sticker.labels");
+        assert_eq!(config.model.parameters, "epoch-99");
+        assert!(toml.get("metadata").is_none());
+        assert_eq!(metadata.name.as_deref(), Some("de-ud"));
+        assert_eq!(metadata.version.as_deref(), Some("1.0.0"));
+        assert_eq!(
+            metadata.checksums.labels.as_deref(),
+            Some("0123456789abcdef")
+        );
+    }
+
+    #[test]
+    fn config_without_metadata() {
+        let data = include_str!("../testdata/sticker.conf");
+        let data = &data[..data.find("[metadata]").unwrap()];
+
+        let (config, _, metadata) = parse_config(data).unwrap();
+
+        assert_eq!(config.model.parameters, "epoch-99");
+        assert!(metadata.name.is_none());
+    }
+
+    #[test]
+    fn config_rejects_unknown_tables() {
+        let data = format!(
+            "{}\n[unknown]\nkey = 1\n",
+            include_str!("../testdata/sticker.conf")
+        );
+        assert!(parse_config(&data).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! Conversion between sentences and lists of token dictionaries.
+//!
+//! This is the format that is used by Stanza and other UD tools. Every
+//! token is represented by a dictionary with the keys `id`, `text`,
+//! `lemma`, `upos`, `xpos`, `feats`, `head`, `deprel`, and `misc`.
+//! Keys of fields without a value are omitted. Multiword tokens are
+//! represented by a dictionary with the keys `id` and `text`, where
+//! `id` is a tuple of the first and last token of the range.
+
+use conllu::graph::Sentence;
+use conllu::token::Token;
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::edit::build;
+use crate::multiword::{add_multiword_token, MultiwordToken};
+use crate::sentence::{features_to_string, misc_to_string, parse_features, parse_misc};
+
+/// Convert a sentence to a list of token dictionaries.
+pub fn to_dicts(
+    py: Python,
+    sentence: &Sentence,
+    multiword_tokens: &[MultiwordToken],
+) -> PyResult<Vec<PyObject>> {
+    let dep_graph = sentence.dep_graph();
+
+    let mut dicts = Vec::with_capacity(sentence.len() + multiword_tokens.len() - 1);
+    for token_idx in 1..sentence.len() {
+        if let Some(mwt) = multiword_tokens.iter().find(|mwt| mwt.first() == token_idx) {
+            let dict = PyDict::new(py);
+            dict.set_item("id", (mwt.first(), mwt.last()))?;
+            dict.set_item("text", mwt.form())?;
+            dicts.push(dict.to_object(py));
+        }
+
+        let token = sentence[token_idx].token().unwrap();
+
+        let dict = PyDict::new(py);
+        dict.set_item("id", token_idx)?;
+        dict.set_item("text", token.form())?;
+
+        if let Some(lemma) = token.lemma() {
+            dict.set_item("lemma", lemma)?;
+        }
+
+        if let Some(upos) = token.upos() {
+            dict.set_item("upos", upos)?;
+        }
+
+        if let Some(xpos) = token.xpos() {
+            dict.set_item("xpos", xpos)?;
+        }
+
+        if let Some(feats) = features_to_string(token) {
+            dict.set_item("feats", feats)?;
+        }
+
+        if let Some(triple) = dep_graph.head(token_idx) {
+            dict.set_item("head", triple.head())?;
+
+            if let Some(relation) = triple.relation() {
+                dict.set_item("deprel", relation)?;
+            }
+        }
+
+        if let Some(misc) = misc_to_string(token) {
+            dict.set_item("misc", misc)?;
+        }
+
+        dicts.push(dict.to_object(py));
+    }
+
+    Ok(dicts)
+}
+
+/// Convert a list of token dictionaries to a sentence.
+///
+/// Returns an error if the token identifiers are not consecutive.
+pub fn from_dicts(dicts: Vec<&PyDict>) -> PyResult<(Sentence, Vec<MultiwordToken>)> {
+    let mut tokens = Vec::with_capacity(dicts.len());
+    let mut arcs = Vec::new();
+    let mut ranges = Vec::new();
+
+    for dict in dicts {
+        let id = dict
+            .get_item("id")
+            .ok_or_else(|| exceptions::PyKeyError::new_err("token does not have an id"))?;
+        let text = dict
+            .get_item("text")
+            .ok_or_else(|| exceptions::PyKeyError::new_err("token does not have a text"))?
+            .extract::<&str>()?;
+
+        if let Ok((first, last)) = id.extract::<(usize, usize)>() {
+            ranges.push(MultiwordToken::new(first, last, text));
+            continue;
+        }
+
+        let id = id.extract::<usize>()?;
+        if id != tokens.len() + 1 {
+            return Err(exceptions::PyValueError::new_err(format!(
+                "expected token with id {}, got: {}",
+                tokens.len() + 1,
+                id
+            )));
+        }
+
+        let mut token = Token::new(text);
+        token.set_lemma(optional_item::<String>(dict, "lemma")?);
+        token.set_upos(optional_item::<String>(dict, "upos")?);
+        token.set_xpos(optional_item::<String>(dict, "xpos")?);
+
+        if let Some(feats) = optional_item::<&str>(dict, "feats")? {
+            parse_features(&mut token, feats)?;
+        }
+
+        if let Some(misc) = optional_item::<&str>(dict, "misc")? {
+            parse_misc(&mut token, misc);
+        }
+
+        if let Some(head) = optional_item::<usize>(dict, "head")? {
+            arcs.push((head, optional_item::<String>(dict, "deprel")?, id));
+        }
+
+        tokens.push(token);
+    }
+
+    if let Some(&(head, _, dependent)) = arcs.iter().find(|&&(head, _, _)| head > tokens.len()) {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "head {} of token {} does not exist",
+            head, dependent
+        )));
+    }
+
+    let sentence = build(tokens, arcs);
+
+    let mut multiword_tokens = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        add_multiword_token(&sentence, &mut multiword_tokens, range)?;
+    }
+
+    Ok((sentence, multiword_tokens))
+}
+
+/// Extract an optional dictionary item.
+///
+/// Items that are absent or `None` are returned as `None`.
+fn optional_item<'a, T>(dict: &'a PyDict, key: &str) -> PyResult<Option<T>>
+where
+    T: FromPyObject<'a>,
+{
+    match dict.get_item(key) {
+        Some(value) if !value.is_none() => value.extract().map(Some),
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,356 @@
+//! Structural changes to sentences.
+//!
+//! `conllu::graph::Sentence` only supports appending tokens, so the
+//! functions in this module rebuild the sentence with renumbered
+//! dependency relations.
+
+use std::collections::{HashMap, HashSet};
+
+use conllu::graph::{DepTriple, Sentence};
+use conllu::token::Token;
+use pyo3::exceptions;
+use pyo3::prelude::*;
+
+use crate::multiword::{add_multiword_token, renumber_multiword_tokens, MultiwordToken};
+
+/// A dependency relation: head, relation, dependent.
+pub(crate) type Relation = (usize, Option<String>, usize);
+
+/// Insert a token with the given form before token `idx`.
+///
+/// Inserting at `idx == sentence.len()` appends the token. The
+/// inserted token is not attached to a head. Returns an error when
+/// the token would be inserted inside a multiword token.
+pub fn insert(
+    sentence: &mut Sentence,
+    multiword_tokens: &mut Vec<MultiwordToken>,
+    idx: usize,
+    form: &str,
+) -> PyResult<()> {
+    if idx == 0 || idx > sentence.len() {
+        return Err(exceptions::PyIndexError::new_err(
+            "token index out of range",
+        ));
+    }
+
+    if let Some(mwt) = multiword_tokens
+        .iter()
+        .find(|mwt| mwt.first() < idx && idx <= mwt.last())
+    {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "cannot insert token before {}, it is inside multiword token {}-{}",
+            idx,
+            mwt.first(),
+            mwt.last()
+        )));
+    }
+
+    let mut tokens = tokens(sentence);
+    tokens.insert(idx - 1, Token::new(form));
+
+    let renumber = |i: usize| if i >= idx { i + 1 } else { i };
+
+    let arcs = arcs(sentence)
+        .into_iter()
+        .map(|(head, rel, dependent)| (renumber(head), rel, renumber(dependent)))
+        .collect();
+
+    *sentence = rebuild(sentence, tokens, arcs);
+    renumber_multiword_tokens(multiword_tokens, renumber, renumber);
+
+    Ok(())
+}
+
+/// Delete the token at `idx`.
+///
+/// Returns an error when the token has dependents, since they would
+/// become orphaned.
+pub fn delete(
+    sentence: &mut Sentence,
+    multiword_tokens: &mut Vec<MultiwordToken>,
+    idx: usize,
+) -> PyResult<()> {
+    check_token_idx(sentence, idx)?;
+
+    if sentence.dep_graph().dependents(idx).next().is_some() {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "cannot delete token {}, it has dependents",
+            idx
+        )));
+    }
+
+    let mut tokens = tokens(sentence);
+    tokens.remove(idx - 1);
+
+    let renumber = |i: usize| if i > idx { i - 1 } else { i };
+
+    let arcs = arcs(sentence)
+        .into_iter()
+        .filter(|&(_, _, dependent)| dependent != idx)
+        .map(|(head, rel, dependent)| (renumber(head), rel, renumber(dependent)))
+        .collect();
+
+    *sentence = rebuild(sentence, tokens, arcs);
+    renumber_multiword_tokens(
+        multiword_tokens,
+        renumber,
+        |i| {
+            if i >= idx {
+                i - 1
+            } else {
+                i
+            }
+        },
+    );
+
+    Ok(())
+}
+
+/// Split the token at `idx` into tokens with the given forms.
+///
+/// The first token takes over the head and dependents of the split
+/// token. If `multiword` is true, a multiword token with the form
+/// of the split token is added.
+pub fn split_token(
+    sentence: &mut Sentence,
+    multiword_tokens: &mut Vec<MultiwordToken>,
+    idx: usize,
+    forms: &[String],
+    multiword: bool,
+) -> PyResult<()> {
+    check_token_idx(sentence, idx)?;
+
+    if forms.len() < 2 {
+        return Err(exceptions::PyValueError::new_err(
+            "a token must be split into at least two tokens",
+        ));
+    }
+
+    let shift = forms.len() - 1;
+    let form = sentence[idx].token().unwrap().form().to_owned();
+
+    let mut tokens = tokens(sentence);
+    tokens.splice(
+        idx - 1..idx,
+        forms.iter().map(|form| Token::new(form.as_str())),
+    );
+
+    let renumber = |i: usize| if i > idx { i + shift } else { i };
+
+    let arcs = arcs(sentence)
+        .into_iter()
+        .map(|(head, rel, dependent)| (renumber(head), rel, renumber(dependent)))
+        .collect();
+
+    let new_sentence = rebuild(sentence, tokens, arcs);
+    let mut new_multiword_tokens = multiword_tokens.clone();
+    renumber_multiword_tokens(&mut new_multiword_tokens, renumber, |i| {
+        if i >= idx {
+            i + shift
+        } else {
+            i
+        }
+    });
+
+    if multiword {
+        add_multiword_token(
+            &new_sentence,
+            &mut new_multiword_tokens,
+            MultiwordToken::new(idx, idx + shift, form),
+        )?;
+    }
+
+    *sentence = new_sentence;
+    *multiword_tokens = new_multiword_tokens;
+
+    Ok(())
+}
+
+/// Merge the tokens `first..=last` into a single token.
+///
+/// The merged token takes over the head of the token in the range
+/// that is attached to a token outside the range. Dependents of the
+/// tokens in the range become dependents of the merged token. An
+/// error is returned when more than one token in the range is
+/// attached outside the range, or when the merge would introduce a
+/// cycle (the head chain of the external head leads back into the
+/// range).
+pub fn merge_tokens(
+    sentence: &mut Sentence,
+    multiword_tokens: &mut Vec<MultiwordToken>,
+    first: usize,
+    last: usize,
+    form: &str,
+) -> PyResult<()> {
+    check_token_idx(sentence, first)?;
+    check_token_idx(sentence, last)?;
+
+    if first >= last {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "merge range must span at least two tokens, got range: {}-{}",
+            first, last
+        )));
+    }
+
+    let in_range = |i: usize| i >= first && i <= last;
+
+    let arcs = arcs(sentence);
+    let external_heads = arcs
+        .iter()
+        .filter(|&&(head, _, dependent)| in_range(dependent) && !in_range(head))
+        .count();
+    if external_heads > 1 {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "cannot merge tokens {}-{}, multiple tokens have a head outside the range",
+            first, last
+        )));
+    }
+
+    let shift = last - first;
+
+    let mut tokens = tokens(sentence);
+    tokens.splice(first - 1..last, std::iter::once(Token::new(form)));
+
+    let renumber = |i: usize| {
+        if i > last {
+            i - shift
+        } else if i > first {
+            first
+        } else {
+            i
+        }
+    };
+
+    let arcs = arcs
+        .into_iter()
+        .filter(|&(head, _, dependent)| !(in_range(head) && in_range(dependent)))
+        .map(|(head, rel, dependent)| (renumber(head), rel, renumber(dependent)))
+        .collect::<Vec<_>>();
+
+    if on_cycle(&arcs, first) {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "cannot merge tokens {}-{}, the merged token would be its own ancestor",
+            first, last
+        )));
+    }
+
+    *sentence = rebuild(sentence, tokens, arcs);
+    renumber_multiword_tokens(multiword_tokens, renumber, renumber);
+
+    Ok(())
+}
+
+/// Get the dependency relations of a sentence.
+fn arcs(sentence: &Sentence) -> Vec<Relation> {
+    let dep_graph = sentence.dep_graph();
+
+    (1..sentence.len())
+        .filter_map(|dependent| dep_graph.head(dependent))
+        .map(|triple| {
+            (
+                triple.head(),
+                triple.relation().map(ToOwned::to_owned),
+                triple.dependent(),
+            )
+        })
+        .collect()
+}
+
+/// Check whether a token is on a cycle of dependency relations.
+pub(crate) fn on_cycle(arcs: &[Relation], token: usize) -> bool {
+    let heads = arcs
+        .iter()
+        .map(|&(head, _, dependent)| (dependent, head))
+        .collect::<HashMap<_, _>>();
+
+    let mut visited = HashSet::new();
+    let mut current = token;
+    while let Some(&head) = heads.get(&current) {
+        if head == token {
+            return true;
+        }
+
+        // Guard against cycles that do not include the token.
+        if !visited.insert(head) {
+            return false;
+        }
+
+        current = head;
+    }
+
+    false
+}
+
+fn check_token_idx(sentence: &Sentence, idx: usize) -> PyResult<()> {
+    if idx == 0 || idx >= sentence.len() {
+        Err(exceptions::PyIndexError::new_err(
+            "token index out of range",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Construct a sentence from tokens and dependency relations.
+pub(crate) fn build(tokens: Vec<Token>, arcs: Vec<Relation>) -> Sentence {
+    let mut sentence = tokens.into_iter().collect::<Sentence>();
+
+    let mut dep_graph = sentence.dep_graph_mut();
+    for (head, rel, dependent) in arcs {
+        dep_graph.add_deprel(DepTriple::new(head, rel, dependent));
+    }
+
+    sentence
+}
+
+/// Construct a sentence from tokens and dependency relations.
+///
+/// The comments are copied from the original sentence.
+pub(crate) fn rebuild(original: &Sentence, tokens: Vec<Token>, arcs: Vec<Relation>) -> Sentence {
+    let mut sentence = build(tokens, arcs);
+    sentence.set_comments(original.comments().to_owned());
+    sentence
+}
+
+/// Get the tokens of a sentence, excluding the root.
+fn tokens(sentence: &Sentence) -> Vec<Token> {
+    (1..sentence.len())
+        .map(|idx| sentence[idx].token().unwrap().clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use conllu::graph::Sentence;
+    use conllu::token::Token;
+
+    use super::insert;
+    use crate::multiword::MultiwordToken;
+
+    fn sentence(forms: &[&str]) -> Sentence {
+        forms.iter().map(|&form| Token::new(form)).collect()
+    }
+
+    #[test]
+    fn insert_inside_multiword_token_is_rejected() {
+        let mut sentence = sentence(&["zu", "dem", "Haus"]);
+        let mut multiword_tokens = vec![MultiwordToken::new(1, 2, "zum")];
+
+        assert!(insert(&mut sentence, &mut multiword_tokens, 2, "x").is_err());
+        assert_eq!(sentence.len(), 4);
+        assert_eq!(multiword_tokens, vec![MultiwordToken::new(1, 2, "zum")]);
+    }
+
+    #[test]
+    fn insert_around_multiword_token_renumbers_range() {
+        let mut sentence = sentence(&["zu", "dem", "Haus"]);
+        let mut multiword_tokens = vec![MultiwordToken::new(1, 2, "zum")];
+
+        insert(&mut sentence, &mut multiword_tokens, 3, "x").unwrap();
+        assert_eq!(multiword_tokens, vec![MultiwordToken::new(1, 2, "zum")]);
+
+        insert(&mut sentence, &mut multiword_tokens, 1, "y").unwrap();
+        assert_eq!(multiword_tokens, vec![MultiwordToken::new(2, 3, "zum")]);
+        assert_eq!(sentence.len(), 6);
+    }
+}
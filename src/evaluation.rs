@@ -0,0 +1,367 @@
+//! Evaluation of annotations against gold-standard annotations.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use conllu::graph::Sentence;
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::layer::{Layer, LayerKind};
+
+/// Label used for tokens without an annotation.
+const NO_LABEL: &str = "_";
+
+/// Evaluation of a single layer.
+#[derive(Default)]
+struct LayerEvaluation {
+    /// Confusion counts, indexed by gold label and predicted label.
+    confusion: BTreeMap<String, BTreeMap<String, usize>>,
+    correct: usize,
+    total: usize,
+
+    /// Number of tokens with the correct head.
+    head_correct: usize,
+
+    /// Number of tokens with the correct head and relation.
+    labeled_correct: usize,
+}
+
+impl LayerEvaluation {
+    fn add(&mut self, gold: Option<String>, predicted: Option<String>) {
+        let gold = gold.unwrap_or_else(|| NO_LABEL.to_owned());
+        let predicted = predicted.unwrap_or_else(|| NO_LABEL.to_owned());
+
+        if gold == predicted {
+            self.correct += 1;
+        }
+        self.total += 1;
+
+        *self
+            .confusion
+            .entry(gold)
+            .or_default()
+            .entry(predicted)
+            .or_default() += 1;
+    }
+
+    fn accuracy(&self) -> f64 {
+        ratio(self.correct, self.total)
+    }
+
+    fn to_dict(&self, py: Python, layer: &Layer) -> PyResult<PyObject> {
+        let dict = PyDict::new(py);
+        dict.set_item("accuracy", self.accuracy())?;
+
+        if *layer.kind() == LayerKind::Dependency {
+            dict.set_item("uas", ratio(self.head_correct, self.total))?;
+            dict.set_item("las", ratio(self.labeled_correct, self.total))?;
+        }
+
+        let mut gold_counts = BTreeMap::new();
+        let mut predicted_counts = BTreeMap::new();
+        for (gold, row) in &self.confusion {
+            for (predicted, &count) in row {
+                *gold_counts.entry(gold.as_str()).or_insert(0) += count;
+                *predicted_counts.entry(predicted.as_str()).or_insert(0) += count;
+            }
+        }
+
+        let labels = gold_counts
+            .keys()
+            .chain(predicted_counts.keys())
+            .collect::<BTreeSet<_>>();
+
+        let label_dict = PyDict::new(py);
+        for &label in labels {
+            let correct = self
+                .confusion
+                .get(label)
+                .and_then(|row| row.get(label))
+                .cloned()
+                .unwrap_or(0);
+            let support = gold_counts.get(label).cloned().unwrap_or(0);
+            let precision = ratio(correct, predicted_counts.get(label).cloned().unwrap_or(0));
+            let recall = ratio(correct, support);
+            let f1 = if precision + recall == 0. {
+                0.
+            } else {
+                2. * precision * recall / (precision + recall)
+            };
+
+            let scores = PyDict::new(py);
+            scores.set_item("precision", precision)?;
+            scores.set_item("recall", recall)?;
+            scores.set_item("f1", f1)?;
+            scores.set_item("support", support)?;
+            label_dict.set_item(label, scores)?;
+        }
+        dict.set_item("labels", label_dict)?;
+
+        dict.set_item("confusion", self.confusion.to_object(py))?;
+
+        Ok(dict.to_object(py))
+    }
+}
+
+/// Evaluate predicted sentences against gold-standard sentences.
+///
+/// Only the given layers are evaluated. An error is returned when the
+/// tokens of the gold-standard and predicted sentences do not align.
+pub fn evaluate(
+    py: Python,
+    gold_sentences: &[&Sentence],
+    predicted_sentences: &[&Sentence],
+    layers: &[Layer],
+) -> PyResult<PyObject> {
+    if gold_sentences.len() != predicted_sentences.len() {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "number of gold sentences ({}) and predicted sentences ({}) differ",
+            gold_sentences.len(),
+            predicted_sentences.len()
+        )));
+    }
+
+    let mut evaluations = layers
+        .iter()
+        .map(|_| LayerEvaluation::default())
+        .collect::<Vec<_>>();
+
+    for (sentence_idx, (gold, predicted)) in
+        gold_sentences.iter().zip(predicted_sentences).enumerate()
+    {
+        check_alignment(sentence_idx, gold, predicted)?;
+
+        for token_idx in 1..gold.len() {
+            for (layer, evaluation) in layers.iter().zip(evaluations.iter_mut()) {
+                evaluation.add(
+                    layer.label(gold, token_idx),
+                    layer.label(predicted, token_idx),
+                );
+
+                if *layer.kind() == LayerKind::Dependency {
+                    let gold_triple = gold.dep_graph().head(token_idx);
+                    let predicted_triple = predicted.dep_graph().head(token_idx);
+
+                    if let (Some(gold_triple), Some(predicted_triple)) =
+                        (gold_triple, predicted_triple)
+                    {
+                        if gold_triple.head() == predicted_triple.head() {
+                            evaluation.head_correct += 1;
+
+                            if gold_triple.relation() == predicted_triple.relation() {
+                                evaluation.labeled_correct += 1;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let report = PyDict::new(py);
+    for (layer, evaluation) in layers.iter().zip(evaluations) {
+        report.set_item(layer.name(), evaluation.to_dict(py, layer)?)?;
+    }
+
+    Ok(report.to_object(py))
+}
+
+/// Accuracy of a layer for two annotators of the same sentences.
+#[derive(Debug, PartialEq)]
+struct LayerDrift {
+    accuracy: f64,
+    reference_accuracy: f64,
+
+    /// Fraction of tokens for which the annotators disagree.
+    disagreement: f64,
+}
+
+impl LayerDrift {
+    fn to_dict(&self, py: Python) -> PyResult<PyObject> {
+        let dict = PyDict::new(py);
+        dict.set_item("accuracy", self.accuracy)?;
+        dict.set_item("reference_accuracy", self.reference_accuracy)?;
+        dict.set_item("drift", self.accuracy - self.reference_accuracy)?;
+        dict.set_item("disagreement", self.disagreement)?;
+        Ok(dict.to_object(py))
+    }
+}
+
+/// Compare the accuracy of predicted sentences to the accuracy of
+/// reference predictions.
+///
+/// Only the given layers are compared. An error is returned when the
+/// tokens of the sentences do not align.
+pub fn accuracy_drift(
+    py: Python,
+    gold_sentences: &[&Sentence],
+    reference_sentences: &[&Sentence],
+    predicted_sentences: &[&Sentence],
+    layers: &[Layer],
+) -> PyResult<PyObject> {
+    let drifts = layer_drifts(
+        gold_sentences,
+        reference_sentences,
+        predicted_sentences,
+        layers,
+    )?;
+
+    let report = PyDict::new(py);
+    for (layer, drift) in layers.iter().zip(drifts) {
+        report.set_item(layer.name(), drift.to_dict(py)?)?;
+    }
+
+    Ok(report.to_object(py))
+}
+
+fn layer_drifts(
+    gold_sentences: &[&Sentence],
+    reference_sentences: &[&Sentence],
+    predicted_sentences: &[&Sentence],
+    layers: &[Layer],
+) -> PyResult<Vec<LayerDrift>> {
+    if gold_sentences.len() != reference_sentences.len()
+        || gold_sentences.len() != predicted_sentences.len()
+    {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "number of gold sentences ({}), reference sentences ({}) and \
+             predicted sentences ({}) differ",
+            gold_sentences.len(),
+            reference_sentences.len(),
+            predicted_sentences.len()
+        )));
+    }
+
+    let mut counts = vec![(0, 0, 0); layers.len()];
+    let mut total = 0;
+    for (sentence_idx, ((gold, reference), predicted)) in gold_sentences
+        .iter()
+        .zip(reference_sentences)
+        .zip(predicted_sentences)
+        .enumerate()
+    {
+        check_alignment(sentence_idx, gold, reference)?;
+        check_alignment(sentence_idx, gold, predicted)?;
+
+        for token_idx in 1..gold.len() {
+            for (layer, (correct, reference_correct, disagreements)) in
+                layers.iter().zip(counts.iter_mut())
+            {
+                let gold_label = layer.label(gold, token_idx);
+                let reference_label = layer.label(reference, token_idx);
+                let predicted_label = layer.label(predicted, token_idx);
+
+                if predicted_label == gold_label {
+                    *correct += 1;
+                }
+                if reference_label == gold_label {
+                    *reference_correct += 1;
+                }
+                if predicted_label != reference_label {
+                    *disagreements += 1;
+                }
+            }
+        }
+
+        total += gold.len() - 1;
+    }
+
+    Ok(counts
+        .into_iter()
+        .map(|(correct, reference_correct, disagreements)| LayerDrift {
+            accuracy: ratio(correct, total),
+            reference_accuracy: ratio(reference_correct, total),
+            disagreement: ratio(disagreements, total),
+        })
+        .collect())
+}
+
+/// Get the layers from `layers` for which a set of sentences has
+/// annotations.
+pub fn annotated_layers(sentences: &[&Sentence], layers: &[Layer]) -> Vec<Layer> {
+    layers
+        .iter()
+        .cloned()
+        .filter(|layer| {
+            sentences
+                .iter()
+                .any(|sentence| (1..sentence.len()).any(|idx| layer.is_annotated(sentence, idx)))
+        })
+        .collect()
+}
+
+fn check_alignment(sentence_idx: usize, gold: &Sentence, predicted: &Sentence) -> PyResult<()> {
+    if gold.len() != predicted.len() {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "sentence {}: gold sentence has {} tokens, predicted sentence has {} tokens",
+            sentence_idx,
+            gold.len() - 1,
+            predicted.len() - 1
+        )));
+    }
+
+    for token_idx in 1..gold.len() {
+        let gold_form = gold[token_idx].token().unwrap().form();
+        let predicted_form = predicted[token_idx].token().unwrap().form();
+
+        if gold_form != predicted_form {
+            return Err(exceptions::PyValueError::new_err(format!(
+                "sentence {}, token {}: gold form '{}' does not match predicted form '{}'",
+                sentence_idx, token_idx, gold_form, predicted_form
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 {
+        0.
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use conllu::graph::Sentence;
+    use conllu::token::{Token, TokenBuilder};
+
+    use super::{layer_drifts, LayerDrift};
+    use crate::layer::Layer;
+
+    fn sentence(tags: &[&str]) -> Sentence {
+        tags.iter()
+            .map(|&tag| Token::from(TokenBuilder::new("a").upos(tag)))
+            .collect()
+    }
+
+    #[test]
+    fn drift_of_layer() {
+        let upos = Layer::select(&Layer::conllu(), &["upos".to_owned()]).unwrap();
+        let gold = sentence(&["DET", "NOUN", "VERB", "ADV"]);
+        let reference = sentence(&["DET", "NOUN", "VERB", "ADJ"]);
+        let predicted = sentence(&["DET", "VERB", "VERB", "NOUN"]);
+
+        assert_eq!(
+            layer_drifts(&[&gold], &[&reference], &[&predicted], &upos).unwrap(),
+            vec![LayerDrift {
+                accuracy: 0.5,
+                reference_accuracy: 0.75,
+                disagreement: 0.5,
+            }]
+        );
+    }
+
+    #[test]
+    fn drift_rejects_misaligned_sentences() {
+        let upos = Layer::select(&Layer::conllu(), &["upos".to_owned()]).unwrap();
+        let gold = sentence(&["DET", "NOUN"]);
+        let predicted = sentence(&["DET"]);
+
+        assert!(layer_drifts(&[&gold], &[&gold], &[&predicted], &upos).is_err());
+        assert!(layer_drifts(&[&gold], &[], &[&gold], &upos).is_err());
+    }
+}
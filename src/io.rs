@@ -1,29 +1,60 @@
 use std::fs::File;
+use std::io::{BufRead, BufReader};
 
 use anyhow::{Context, Result};
-use sticker2::config::{Config, PretrainConfig};
+use conllu::graph::Sentence;
+use conllu::io::{ReadSentence, Reader};
+use sticker2::config::{Config, PositionEmbeddings, PretrainConfig, Tokenizer};
 use sticker2::encoders::Encoders;
 use sticker2::input::Tokenize;
 use sticker2::model::bert::BertModel;
 use tch::nn::VarStore;
 use tch::Device;
 
+use crate::cache::load_cached_parameters;
+
 /// Wrapper around different parts of a model.
 pub struct Model {
     pub encoders: Encoders,
     pub model: BertModel,
-    pub tokenizer: Box<dyn Tokenize>,
     pub vs: VarStore,
 }
 
 impl Model {
     /// Load a model on the given device.
     pub fn load(config: &Config, device: Device) -> Result<Model> {
+        let mut model = Self::construct(config, device)?;
+
+        model
+            .vs
+            .load(&config.model.parameters)
+            .context("Cannot load model parameters")?;
+
+        model.vs.freeze();
+
+        Ok(model)
+    }
+
+    /// Load a model from a parameter cache.
+    ///
+    /// The parameters are read from the parameter cache at `cache`,
+    /// which is created when it does not exist.
+    pub fn load_cached(config: &Config, cache: &str) -> Result<Model> {
+        let mut model = Self::construct(config, Device::Cpu)?;
+
+        model.vs.freeze();
+
+        load_cached_parameters(&mut model.vs, &config.model.parameters, cache)?;
+
+        Ok(model)
+    }
+
+    /// Construct a model with uninitialized parameters.
+    fn construct(config: &Config, device: Device) -> Result<Model> {
         let encoders = load_encoders(&config)?;
-        let tokenizer = load_tokenizer(&config)?;
         let pretrain_config = load_pretrain_config(&config)?;
 
-        let mut vs = VarStore::new(device);
+        let vs = VarStore::new(device);
 
         let model = BertModel::new(
             vs.root(),
@@ -34,20 +65,51 @@ impl Model {
         )
         .context("Cannot construct model")?;
 
-        vs.load(&config.model.parameters)
-            .context("Cannot load model parameters")?;
-
-        vs.freeze();
-
         Ok(Model {
             encoders,
             model,
-            tokenizer,
             vs,
         })
     }
 }
 
+/// Load the word piece vocabulary of a model.
+///
+/// Returns `None` when the tokenizer does not use a plain-text
+/// vocabulary with one piece per line.
+pub fn load_piece_vocab(config: &Config) -> Result<Option<Vec<String>>> {
+    let vocab = match &config.input.tokenizer {
+        Tokenizer::Bert { vocab } => vocab,
+        _ => return Ok(None),
+    };
+
+    let f = File::open(vocab).context(format!("Cannot open vocabulary: {}", vocab))?;
+
+    BufReader::new(f)
+        .lines()
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+        .context(format!("Cannot read vocabulary: {}", vocab))
+}
+
+/// Get the maximum number of pieces that the model can process.
+///
+/// Returns `None` when the model does not have a maximum length.
+pub fn max_pieces(config: &Config) -> Result<Option<usize>> {
+    if let PositionEmbeddings::Sinusoidal { .. } = config.model.position_embeddings {
+        return Ok(None);
+    }
+
+    let max_pieces = match load_pretrain_config(config)? {
+        PretrainConfig::Albert(config) => config.max_position_embeddings as usize,
+        PretrainConfig::Bert(config) => config.max_position_embeddings as usize,
+        // XLM-RoBERTa reserves the first two positions.
+        PretrainConfig::XlmRoberta(config) => config.max_position_embeddings as usize - 2,
+    };
+
+    Ok(Some(max_pieces))
+}
+
 pub fn load_pretrain_config(config: &Config) -> Result<PretrainConfig> {
     config
         .model
@@ -74,6 +136,16 @@ fn load_encoders(config: &Config) -> Result<Encoders> {
     Ok(encoders)
 }
 
+/// Read sentences from a CoNLL-U file.
+pub fn read_sentences(path: &str) -> Result<Vec<Sentence>> {
+    let f = File::open(path).context(format!("Cannot open CoNLL-U file: {}", path))?;
+
+    Reader::new(BufReader::new(f))
+        .sentences()
+        .collect::<Result<Vec<_>, _>>()
+        .context(format!("Cannot read sentences from: {}", path))
+}
+
 pub fn load_tokenizer(config: &Config) -> Result<Box<dyn Tokenize>> {
     config
         .tokenizer()
@@ -0,0 +1,184 @@
+//! JSON serialization of sentences.
+//!
+//! The serialization format is documented in `Sentence.to_json`.
+
+use std::collections::BTreeMap;
+
+use conllu::graph::{Comment, Sentence};
+use conllu::token::Token;
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::edit::build;
+use crate::multiword::{add_multiword_token, MultiwordToken};
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum JsonComment {
+    AttrVal { attr: String, val: String },
+    String(String),
+}
+
+#[derive(Deserialize, Serialize)]
+struct JsonMultiwordToken {
+    first: usize,
+    last: usize,
+    form: String,
+}
+
+#[derive(Deserialize, Serialize)]
+struct JsonToken {
+    id: usize,
+    form: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    lemma: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    upos: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    xpos: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    features: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    misc: BTreeMap<String, Option<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    head: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deprel: Option<String>,
+}
+
+/// Serializable representation of a sentence.
+#[derive(Deserialize, Serialize)]
+pub struct JsonSentence {
+    #[serde(default)]
+    comments: Vec<JsonComment>,
+    tokens: Vec<JsonToken>,
+    #[serde(default)]
+    multiword_tokens: Vec<JsonMultiwordToken>,
+}
+
+impl JsonSentence {
+    pub fn new(sentence: &Sentence, multiword_tokens: &[MultiwordToken]) -> Self {
+        let comments = sentence
+            .comments()
+            .iter()
+            .map(|comment| match comment {
+                Comment::AttrVal { attr, val } => JsonComment::AttrVal {
+                    attr: attr.clone(),
+                    val: val.clone(),
+                },
+                Comment::String(comment) => JsonComment::String(comment.clone()),
+            })
+            .collect();
+
+        let dep_graph = sentence.dep_graph();
+        let tokens = (1..sentence.len())
+            .map(|token_idx| {
+                let token = sentence[token_idx].token().unwrap();
+                let triple = dep_graph.head(token_idx);
+
+                JsonToken {
+                    id: token_idx,
+                    form: token.form().to_owned(),
+                    lemma: token.lemma().map(ToOwned::to_owned),
+                    upos: token.upos().map(ToOwned::to_owned),
+                    xpos: token.xpos().map(ToOwned::to_owned),
+                    features: token
+                        .features()
+                        .iter()
+                        .map(|(f, v)| (f.clone(), v.clone()))
+                        .collect(),
+                    misc: token
+                        .misc()
+                        .iter()
+                        .map(|(f, v)| (f.clone(), v.clone()))
+                        .collect(),
+                    head: triple.as_ref().map(|triple| triple.head()),
+                    deprel: triple
+                        .as_ref()
+                        .and_then(|triple| triple.relation().map(ToOwned::to_owned)),
+                }
+            })
+            .collect();
+
+        let multiword_tokens = multiword_tokens
+            .iter()
+            .map(|mwt| JsonMultiwordToken {
+                first: mwt.first(),
+                last: mwt.last(),
+                form: mwt.form().to_owned(),
+            })
+            .collect();
+
+        JsonSentence {
+            comments,
+            tokens,
+            multiword_tokens,
+        }
+    }
+
+    /// Convert to a sentence and its multiword tokens.
+    pub fn into_sentence(self) -> PyResult<(Sentence, Vec<MultiwordToken>)> {
+        let mut tokens = Vec::with_capacity(self.tokens.len());
+        let mut arcs = Vec::new();
+
+        for json_token in self.tokens {
+            if json_token.id != tokens.len() + 1 {
+                return Err(exceptions::PyValueError::new_err(format!(
+                    "expected token with id {}, got: {}",
+                    tokens.len() + 1,
+                    json_token.id
+                )));
+            }
+
+            let mut token = Token::new(json_token.form);
+            token.set_lemma(json_token.lemma);
+            token.set_upos(json_token.upos);
+            token.set_xpos(json_token.xpos);
+
+            for (feature, value) in json_token.features {
+                token.features_mut().insert(feature, value);
+            }
+
+            for (feature, value) in json_token.misc {
+                token.misc_mut().insert(feature, value);
+            }
+
+            if let Some(head) = json_token.head {
+                arcs.push((head, json_token.deprel, json_token.id));
+            }
+
+            tokens.push(token);
+        }
+
+        if let Some(&(head, _, dependent)) = arcs.iter().find(|&&(head, _, _)| head > tokens.len())
+        {
+            return Err(exceptions::PyValueError::new_err(format!(
+                "head {} of token {} does not exist",
+                head, dependent
+            )));
+        }
+
+        let mut sentence = build(tokens, arcs);
+        sentence.set_comments(
+            self.comments
+                .into_iter()
+                .map(|comment| match comment {
+                    JsonComment::AttrVal { attr, val } => Comment::AttrVal { attr, val },
+                    JsonComment::String(comment) => Comment::String(comment),
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        let mut multiword_tokens = Vec::with_capacity(self.multiword_tokens.len());
+        for mwt in self.multiword_tokens {
+            add_multiword_token(
+                &sentence,
+                &mut multiword_tokens,
+                MultiwordToken::new(mwt.first, mwt.last, mwt.form),
+            )?;
+        }
+
+        Ok((sentence, multiword_tokens))
+    }
+}
@@ -0,0 +1,331 @@
+use conllu::graph::Sentence;
+use conllu::token::Token;
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use sticker2::encoders::{EncoderType, EncodersConfig};
+use sticker_encoders::layer::{Layer as SequenceLayer, LayerValue};
+
+use crate::edit::{on_cycle, rebuild};
+use crate::sentence::{features_to_string, parse_features};
+
+/// The annotation that a layer covers.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LayerKind {
+    /// Dependency head and relation.
+    Dependency,
+
+    /// Lemma.
+    Lemma,
+
+    /// A token layer, such as the part-of-speech tag or a feature.
+    Sequence(SequenceLayer),
+}
+
+/// Annotation layer of a token.
+///
+/// The layers that a model annotates correspond to its encoders and
+/// have the names of the encoders.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Layer {
+    name: String,
+    kind: LayerKind,
+}
+
+impl Layer {
+    fn new(name: impl Into<String>, kind: LayerKind) -> Self {
+        Layer {
+            name: name.into(),
+            kind,
+        }
+    }
+
+    /// The layers of the CoNLL-U format.
+    ///
+    /// These layers are used when no model configuration is available.
+    pub fn conllu() -> Vec<Layer> {
+        vec![
+            Layer::new("lemma", LayerKind::Lemma),
+            Layer::new("upos", LayerKind::Sequence(SequenceLayer::UPos)),
+            Layer::new("xpos", LayerKind::Sequence(SequenceLayer::XPos)),
+            Layer::new("feats", LayerKind::Sequence(SequenceLayer::FeatureString)),
+            Layer::new("deprel", LayerKind::Dependency),
+        ]
+    }
+
+    /// The layers that are annotated by a model's encoders.
+    pub fn from_encoders(encoders: &EncodersConfig) -> Vec<Layer> {
+        encoders
+            .iter()
+            .map(|encoder| {
+                let kind = match &encoder.encoder {
+                    EncoderType::Dependency { .. } => LayerKind::Dependency,
+                    EncoderType::Lemma(_) | EncoderType::TdzLemma(_) => LayerKind::Lemma,
+                    EncoderType::Sequence(layer) => LayerKind::Sequence(layer.clone()),
+                };
+
+                Layer::new(encoder.name.clone(), kind)
+            })
+            .collect()
+    }
+
+    /// Select layers from `available` by their names.
+    pub fn select(available: &[Layer], names: &[String]) -> PyResult<Vec<Layer>> {
+        names
+            .iter()
+            .map(|name| {
+                available
+                    .iter()
+                    .find(|layer| &layer.name == name)
+                    .cloned()
+                    .ok_or_else(|| {
+                        exceptions::PyValueError::new_err(format!(
+                            "unknown layer '{}', expected one of: {}",
+                            name,
+                            available
+                                .iter()
+                                .map(Layer::name)
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ))
+                    })
+            })
+            .collect()
+    }
+
+    /// Get the kind of the layer.
+    pub fn kind(&self) -> &LayerKind {
+        &self.kind
+    }
+
+    /// Get the name of the layer.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the label of a token in this layer.
+    ///
+    /// For the dependency layer, this is the relation of the token to
+    /// its head. The default value of a feature layer is not used, so a
+    /// token without the feature does not have a label.
+    pub fn label(&self, sentence: &Sentence, token_idx: usize) -> Option<String> {
+        let token = sentence[token_idx].token()?;
+
+        match &self.kind {
+            LayerKind::Dependency => sentence
+                .dep_graph()
+                .head(token_idx)
+                .and_then(|triple| triple.relation().map(ToOwned::to_owned)),
+            LayerKind::Lemma => token.lemma().map(ToOwned::to_owned),
+            LayerKind::Sequence(SequenceLayer::FeatureString) => features_to_string(token),
+            LayerKind::Sequence(SequenceLayer::Feature { feature, .. }) => {
+                token.features().get(feature).cloned()
+            }
+            LayerKind::Sequence(SequenceLayer::Misc { feature, .. }) => {
+                token.misc().get(feature).cloned().flatten()
+            }
+            LayerKind::Sequence(layer) => token.value(layer),
+        }
+    }
+
+    /// Check whether a token has an annotation in this layer.
+    pub fn is_annotated(&self, sentence: &Sentence, token_idx: usize) -> bool {
+        match self.kind {
+            LayerKind::Dependency => sentence.dep_graph().head(token_idx).is_some(),
+            _ => self.label(sentence, token_idx).is_some(),
+        }
+    }
+
+    /// Set the label of a token in this layer.
+    ///
+    /// Dependency relations cannot be set per token.
+    pub fn set_label(&self, token: &mut Token, label: &str) -> PyResult<()> {
+        match &self.kind {
+            LayerKind::Dependency => {
+                return Err(exceptions::PyValueError::new_err(format!(
+                    "cannot set the label of dependency layer '{}'",
+                    self.name
+                )))
+            }
+            LayerKind::Lemma => {
+                token.set_lemma(Some(label));
+            }
+            LayerKind::Sequence(SequenceLayer::FeatureString) => {
+                token.features_mut().clear();
+                parse_features(token, label)?;
+            }
+            LayerKind::Sequence(layer) => token.set_value(layer, label),
+        }
+
+        Ok(())
+    }
+
+    /// Copy the annotation of this layer from one token to another.
+    ///
+    /// Dependency relations are not copied, since they are not stored
+    /// in tokens.
+    fn copy(&self, from: &Token, to: &mut Token) {
+        match &self.kind {
+            LayerKind::Dependency => (),
+            LayerKind::Lemma => {
+                to.set_lemma(from.lemma());
+            }
+            LayerKind::Sequence(SequenceLayer::UPos) => {
+                to.set_upos(from.upos());
+            }
+            LayerKind::Sequence(SequenceLayer::XPos) => {
+                to.set_xpos(from.xpos());
+            }
+            LayerKind::Sequence(SequenceLayer::FeatureString) => {
+                *to.features_mut() = from.features().clone();
+            }
+            LayerKind::Sequence(SequenceLayer::Feature { feature, .. }) => {
+                match from.features().get(feature) {
+                    Some(value) => to.features_mut().insert(feature.clone(), value.clone()),
+                    None => to.features_mut().remove(feature),
+                };
+            }
+            LayerKind::Sequence(SequenceLayer::Misc { feature, .. }) => {
+                match from.misc().get(feature) {
+                    Some(value) => to.misc_mut().insert(feature.clone(), value.clone()),
+                    None => to.misc_mut().remove(feature),
+                };
+            }
+        }
+    }
+}
+
+/// Merge predicted annotations into a sentence.
+///
+/// Only annotations of the given layers are taken from `predicted`. If
+/// `overwrite` is false, annotations of a layer are only taken from
+/// `predicted` for tokens that are not annotated in that layer.
+///
+/// The head and relation of a token are always taken from the same
+/// sentence. When the relations of the original and predicted
+/// sentences are combined, an error is returned if the combination is
+/// not a tree: a token would be its own ancestor or more than one
+/// token would be attached to the root.
+pub fn merge_layers(
+    original: &Sentence,
+    predicted: &Sentence,
+    layers: &[Layer],
+    overwrite: bool,
+) -> PyResult<Sentence> {
+    let mut tokens = Vec::with_capacity(original.len() - 1);
+    let mut arcs = Vec::new();
+    let mut original_arcs = false;
+    let mut predicted_arcs = false;
+
+    for token_idx in 1..original.len() {
+        let update = |layer: &Layer| overwrite || !layer.is_annotated(original, token_idx);
+
+        let mut token = original[token_idx].token().unwrap().clone();
+        let predicted_token = predicted[token_idx].token().unwrap();
+
+        for layer in layers.iter().filter(|layer| update(layer)) {
+            layer.copy(predicted_token, &mut token);
+        }
+
+        let update_dependency = layers
+            .iter()
+            .any(|layer| layer.kind == LayerKind::Dependency && update(layer));
+        let source = if update_dependency {
+            predicted
+        } else {
+            original
+        };
+        if let Some(triple) = source.dep_graph().head(token_idx) {
+            if update_dependency {
+                predicted_arcs = true;
+            } else {
+                original_arcs = true;
+            }
+
+            arcs.push((
+                triple.head(),
+                triple.relation().map(ToOwned::to_owned),
+                token_idx,
+            ));
+        }
+
+        tokens.push(token);
+    }
+
+    if original_arcs && predicted_arcs {
+        if let Some(&(_, _, dependent)) = arcs
+            .iter()
+            .find(|&&(_, _, dependent)| on_cycle(&arcs, dependent))
+        {
+            return Err(exceptions::PyValueError::new_err(format!(
+                "predicted dependency relations conflict with existing relations, \
+                 token {} would be its own ancestor",
+                dependent
+            )));
+        }
+
+        if arcs.iter().filter(|&&(head, _, _)| head == 0).count() > 1 {
+            return Err(exceptions::PyValueError::new_err(
+                "predicted dependency relations conflict with existing relations, \
+                 multiple tokens would be attached to the root",
+            ));
+        }
+    }
+
+    Ok(rebuild(original, tokens, arcs))
+}
+
+#[cfg(test)]
+mod tests {
+    use conllu::graph::Sentence;
+    use conllu::token::Token;
+    use sticker_encoders::layer::Layer as SequenceLayer;
+
+    use super::{merge_layers, Layer, LayerKind};
+
+    fn sentence(tokens: Vec<Token>) -> Sentence {
+        tokens.into_iter().collect()
+    }
+
+    fn with_feature(form: &str, value: &str) -> Token {
+        let mut token = Token::new(form);
+        token
+            .features_mut()
+            .insert("Number".to_owned(), value.to_owned());
+        token
+    }
+
+    fn number_layer() -> Layer {
+        Layer::new(
+            "number",
+            LayerKind::Sequence(SequenceLayer::feature(
+                "Number".to_owned(),
+                Some("Sing".to_owned()),
+            )),
+        )
+    }
+
+    #[test]
+    fn absent_feature_is_not_annotated() {
+        let layer = number_layer();
+        let sentence = sentence(vec![Token::new("Hunde"), with_feature("bellen", "Plur")]);
+
+        assert_eq!(layer.label(&sentence, 1), None);
+        assert!(!layer.is_annotated(&sentence, 1));
+        assert_eq!(layer.label(&sentence, 2), Some("Plur".to_owned()));
+        assert!(layer.is_annotated(&sentence, 2));
+    }
+
+    #[test]
+    fn absent_feature_is_filled_without_overwriting() {
+        let layer = number_layer();
+        let original = sentence(vec![Token::new("Hunde"), with_feature("bellen", "Plur")]);
+        let predicted = sentence(vec![
+            with_feature("Hunde", "Plur"),
+            with_feature("bellen", "Sing"),
+        ]);
+
+        let merged = merge_layers(&original, &predicted, &[layer.clone()], false).unwrap();
+        assert_eq!(layer.label(&merged, 1), Some("Plur".to_owned()));
+        assert_eq!(layer.label(&merged, 2), Some("Plur".to_owned()));
+    }
+}
@@ -0,0 +1,357 @@
+//! Handling of sentences that exceed the maximum length of a model.
+//!
+//! Transformer models with learned position embeddings can only
+//! process a limited number of word pieces. Sentences that exceed this
+//! limit are split into overlapping windows, truncated, or skipped.
+
+use std::ops::Range;
+
+use conllu::graph::Sentence;
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use sticker2::input::SentenceWithPieces;
+
+use crate::edit::rebuild;
+use crate::tree::HeadCandidate;
+
+/// Misc feature that marks sentences that were not annotated.
+const SKIPPED_FEATURE: &str = "Sticker2Skipped";
+
+/// Policy for sentences that exceed the maximum length.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LengthPolicy {
+    /// Raise an error.
+    Error,
+
+    /// Skip the sentence, marking its first token in `misc`.
+    Skip,
+
+    /// Only annotate the tokens that fit, with a warning.
+    Truncate,
+
+    /// Annotate overlapping windows and stitch the predictions.
+    Window,
+}
+
+impl LengthPolicy {
+    /// Get a policy by its name.
+    pub fn from_name(name: &str) -> PyResult<Self> {
+        match name {
+            "error" => Ok(LengthPolicy::Error),
+            "skip" => Ok(LengthPolicy::Skip),
+            "truncate" => Ok(LengthPolicy::Truncate),
+            "window" => Ok(LengthPolicy::Window),
+            _ => Err(exceptions::PyValueError::new_err(format!(
+                "unknown length policy '{}', expected one of: error, skip, truncate, window",
+                name
+            ))),
+        }
+    }
+
+    /// Get the token ranges of a sentence that should be annotated.
+    ///
+    /// This method should only be used for sentences that exceed the
+    /// maximum number of pieces. `special_pieces` is the number of
+    /// pieces that the tokenizer adds to every sentence. The ranges use
+    /// 1-based token indices.
+    pub fn windows(
+        self,
+        sentence: &SentenceWithPieces,
+        max_pieces: usize,
+        special_pieces: usize,
+    ) -> PyResult<Vec<Range<usize>>> {
+        match self {
+            LengthPolicy::Error => Err(exceptions::PyValueError::new_err(format!(
+                "sentence has {} pieces, the model supports at most {} pieces",
+                sentence.pieces.len(),
+                max_pieces
+            ))),
+            LengthPolicy::Skip => Ok(Vec::new()),
+            LengthPolicy::Truncate => {
+                let mut windows = windows(sentence, max_pieces, special_pieces)?;
+                windows.truncate(1);
+
+                Python::with_gil(|py| {
+                    PyErr::warn(
+                        py,
+                        py.get_type::<exceptions::PyUserWarning>(),
+                        &format!(
+                            "sentence has {} pieces, only annotating the first {} of {} tokens",
+                            sentence.pieces.len(),
+                            windows[0].len(),
+                            sentence.token_offsets.len()
+                        ),
+                        0,
+                    )
+                })?;
+
+                Ok(windows)
+            }
+            LengthPolicy::Window => windows(sentence, max_pieces, special_pieces),
+        }
+    }
+}
+
+/// Mark a sentence as skipped in the `misc` column of its first token.
+pub fn mark_skipped(sentence: &mut Sentence) {
+    if sentence.len() > 1 {
+        sentence[1]
+            .token_mut()
+            .unwrap()
+            .misc_mut()
+            .insert(SKIPPED_FEATURE.to_owned(), Some("TooLong".to_owned()));
+    }
+}
+
+/// Stitch the annotations of windows into the original sentence.
+///
+/// Every token takes its annotations from the window in which it has
+/// the most context. Tokens that are not in any window retain their
+/// original annotations.
+pub fn stitch(original: &Sentence, windows: &[(Range<usize>, Sentence)]) -> Sentence {
+    let n_tokens = original.len() - 1;
+    let original_graph = original.dep_graph();
+
+    let mut tokens = Vec::with_capacity(n_tokens);
+    let mut arcs = Vec::with_capacity(n_tokens);
+    for dependent in 1..=n_tokens {
+        match best_window(windows, dependent, n_tokens) {
+            Some((range, predicted)) => {
+                let idx = dependent - range.start + 1;
+                tokens.push(predicted[idx].token().unwrap().clone());
+
+                if let Some(triple) = predicted.dep_graph().head(idx) {
+                    let head = if triple.head() == 0 {
+                        0
+                    } else {
+                        triple.head() + range.start - 1
+                    };
+                    arcs.push((head, triple.relation().map(ToOwned::to_owned), dependent));
+                }
+            }
+            None => {
+                tokens.push(original[dependent].token().unwrap().clone());
+
+                if let Some(triple) = original_graph.head(dependent) {
+                    arcs.push((
+                        triple.head(),
+                        triple.relation().map(ToOwned::to_owned),
+                        dependent,
+                    ));
+                }
+            }
+        }
+    }
+
+    rebuild(original, tokens, arcs)
+}
+
+/// Stitch the head candidates of windows.
+///
+/// Every token takes its candidates from the same window as its
+/// annotations in `stitch`. Tokens that are not in any window have
+/// their original head, if any, as the only candidate.
+pub fn stitch_candidates(
+    original: &Sentence,
+    windows: &[(Range<usize>, Vec<Vec<HeadCandidate>>)],
+) -> Vec<Vec<HeadCandidate>> {
+    let n_tokens = original.len() - 1;
+    let original_graph = original.dep_graph();
+
+    (1..=n_tokens)
+        .map(
+            |dependent| match best_window(windows, dependent, n_tokens) {
+                Some((range, candidates)) => candidates[dependent - range.start]
+                    .iter()
+                    .map(|candidate| HeadCandidate {
+                        head: if candidate.head == 0 {
+                            0
+                        } else {
+                            candidate.head + range.start - 1
+                        },
+                        ..candidate.clone()
+                    })
+                    .collect(),
+                None => original_graph
+                    .head(dependent)
+                    .map(|triple| HeadCandidate {
+                        head: triple.head(),
+                        relation: triple.relation().unwrap_or_default().to_owned(),
+                        prob: 1.,
+                    })
+                    .into_iter()
+                    .collect(),
+            },
+        )
+        .collect()
+}
+
+/// Get the window in which a token has the most context.
+fn best_window<T>(
+    windows: &[(Range<usize>, T)],
+    token: usize,
+    n_tokens: usize,
+) -> Option<&(Range<usize>, T)> {
+    windows
+        .iter()
+        .filter(|(range, _)| range.contains(&token))
+        .max_by_key(|(range, _)| context(range, token, n_tokens))
+}
+
+/// Get the number of context tokens of a token in a window.
+///
+/// The context is the minimum of the left and right context. Sentence
+/// boundaries count as unlimited context.
+fn context(range: &Range<usize>, token: usize, n_tokens: usize) -> usize {
+    let left = if range.start == 1 {
+        usize::MAX
+    } else {
+        token - range.start
+    };
+
+    let right = if range.end == n_tokens + 1 {
+        usize::MAX
+    } else {
+        range.end - token - 1
+    };
+
+    left.min(right)
+}
+
+/// Split a sentence into overlapping windows that fit in the model.
+///
+/// Consecutive windows overlap by half a window, windows that are
+/// contained in the previous window are left out. The special pieces
+/// that precede the first token (such as the classification piece)
+/// and follow the last token (such as the end-of-sentence piece) are
+/// added to every window by the tokenizer, so they are subtracted from
+/// the piece budget of each window.
+fn windows(
+    sentence: &SentenceWithPieces,
+    max_pieces: usize,
+    special_pieces: usize,
+) -> PyResult<Vec<Range<usize>>> {
+    let offsets = &sentence.token_offsets;
+    let n_tokens = offsets.len();
+
+    let prefix = offsets.first().cloned().unwrap_or(0);
+    let suffix = special_pieces.saturating_sub(prefix);
+    let budget = max_pieces.saturating_sub(special_pieces);
+
+    let piece_counts = offsets
+        .iter()
+        .enumerate()
+        .map(|(idx, &offset)| {
+            offsets
+                .get(idx + 1)
+                .cloned()
+                .unwrap_or_else(|| sentence.pieces.len() - suffix)
+                - offset
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(idx) = piece_counts.iter().position(|&count| count > budget) {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "token {} has {} pieces, which exceeds the maximum of {} pieces",
+            idx + 1,
+            piece_counts[idx],
+            budget
+        )));
+    }
+
+    let mut windows = Vec::new();
+    let mut start = 0;
+    loop {
+        let mut end = start;
+        let mut len = 0;
+        while end < n_tokens && len + piece_counts[end] <= budget {
+            len += piece_counts[end];
+            end += 1;
+        }
+
+        // A window that is contained in the previous window does not
+        // add any context.
+        let contained = windows
+            .last()
+            .map(|previous: &Range<usize>| end < previous.end)
+            .unwrap_or(false);
+        if !contained {
+            windows.push(start + 1..end + 1);
+        }
+
+        if end == n_tokens {
+            break;
+        }
+
+        start += ((end - start) / 2).max(1);
+    }
+
+    Ok(windows)
+}
+
+#[cfg(test)]
+mod tests {
+    use conllu::graph::Sentence;
+    use conllu::token::Token;
+    use ndarray::Array1;
+    use sticker2::input::SentenceWithPieces;
+
+    use super::windows;
+
+    /// Construct a sentence with the given number of pieces per token,
+    /// surrounded by `prefix` and `suffix` special pieces.
+    fn with_pieces(piece_counts: &[usize], prefix: usize, suffix: usize) -> SentenceWithPieces {
+        let mut token_offsets = Vec::with_capacity(piece_counts.len());
+        let mut n_pieces = prefix;
+        for &count in piece_counts {
+            token_offsets.push(n_pieces);
+            n_pieces += count;
+        }
+
+        SentenceWithPieces {
+            pieces: Array1::zeros((n_pieces + suffix,)),
+            sentence: piece_counts
+                .iter()
+                .map(|_| Token::new("a"))
+                .collect::<Sentence>(),
+            token_offsets,
+        }
+    }
+
+    #[test]
+    fn windows_fill_budget_exactly() {
+        // Budget: 6 - 2 special pieces = 4 pieces.
+        let sentence = with_pieces(&[1, 1, 1, 1, 1, 1], 1, 1);
+        assert_eq!(windows(&sentence, 6, 2).unwrap(), vec![1..5, 3..7]);
+    }
+
+    #[test]
+    fn windows_subtract_suffix() {
+        // Without subtracting the suffix, the last window would hold
+        // 4 tokens and exceed the maximum with the end piece.
+        let sentence = with_pieces(&[1, 1, 1, 1, 1], 1, 1);
+        let windows = windows(&sentence, 5, 2).unwrap();
+        assert_eq!(windows, vec![1..4, 2..5, 3..6]);
+        assert!(windows.iter().all(|window| window.len() + 2 <= 5));
+    }
+
+    #[test]
+    fn windows_count_pieces_of_last_token() {
+        // The last token has two pieces, followed by the end piece. The
+        // window 2..3 is contained in the first window and left out.
+        let sentence = with_pieces(&[1, 1, 2], 0, 1);
+        assert_eq!(windows(&sentence, 3, 1).unwrap(), vec![1..3, 3..4]);
+    }
+
+    #[test]
+    fn token_at_budget_is_accepted() {
+        let sentence = with_pieces(&[1, 3, 1], 1, 1);
+        assert_eq!(windows(&sentence, 5, 2).unwrap(), vec![1..2, 2..3, 3..4]);
+    }
+
+    #[test]
+    fn token_over_budget_is_rejected() {
+        let sentence = with_pieces(&[1, 4, 1], 1, 1);
+        assert!(windows(&sentence, 5, 2).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+//! Lexicon restrictions of predicted labels.
+
+use std::collections::{HashMap, HashSet};
+
+use pyo3::exceptions;
+use pyo3::prelude::*;
+
+use crate::layer::{Layer, LayerKind};
+
+/// Lexicon of admissible labels per word form for a layer.
+pub struct Lexicon {
+    layer: Layer,
+    forms: HashMap<String, Vec<String>>,
+    unknown: Option<Vec<String>>,
+}
+
+impl Lexicon {
+    /// Construct a lexicon for a layer.
+    ///
+    /// The first admissible label of a form is used when the model
+    /// does not know any of the admissible labels. If `unknown` is
+    /// provided, the labels of forms that are not in the lexicon are
+    /// restricted to `unknown`.
+    pub fn new(
+        layer: Layer,
+        forms: HashMap<String, Vec<String>>,
+        unknown: Option<Vec<String>>,
+    ) -> PyResult<Self> {
+        if *layer.kind() == LayerKind::Dependency {
+            return Err(exceptions::PyValueError::new_err(
+                "lexicons are not supported for dependency layers",
+            ));
+        }
+
+        if let Some((form, _)) = forms.iter().find(|(_, labels)| labels.is_empty()) {
+            return Err(exceptions::PyValueError::new_err(format!(
+                "lexicon entry for '{}' does not have any labels",
+                form
+            )));
+        }
+
+        if unknown.as_ref().map(Vec::is_empty).unwrap_or(false) {
+            return Err(exceptions::PyValueError::new_err(
+                "labels for unknown words cannot be empty",
+            ));
+        }
+
+        Ok(Lexicon {
+            layer,
+            forms,
+            unknown,
+        })
+    }
+
+    /// Get the admissible labels for a form.
+    ///
+    /// If the form is not in the lexicon, its lowercased form is
+    /// looked up, so that capitalized forms at the start of a sentence
+    /// use the entry of the lowercased form. `None` is returned when
+    /// the labels of the form are not restricted.
+    pub fn labels(&self, form: &str) -> Option<&[String]> {
+        self.forms
+            .get(form)
+            .or_else(|| self.forms.get(&form.to_lowercase()))
+            .or_else(|| self.unknown.as_ref())
+            .map(Vec::as_slice)
+    }
+
+    /// Get the layer of the lexicon.
+    pub fn layer(&self) -> &Layer {
+        &self.layer
+    }
+}
+
+/// Construct lexicons from Python dictionaries.
+///
+/// `lexicons` maps layer names to lexicons, `unknown` maps layer
+/// names to the admissible labels of words that are not in the
+/// lexicon. The layers are looked up in `layers`.
+pub fn lexicons_from_dicts(
+    lexicons: HashMap<String, HashMap<String, Vec<String>>>,
+    mut unknown: HashMap<String, Vec<String>>,
+    layers: &[Layer],
+) -> PyResult<Vec<Lexicon>> {
+    let lexicon_layers = lexicons.keys().cloned().collect::<HashSet<_>>();
+    if let Some(layer) = unknown
+        .keys()
+        .find(|layer| !lexicon_layers.contains(*layer))
+    {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "unknown word labels given for layer without lexicon: {}",
+            layer
+        )));
+    }
+
+    lexicons
+        .into_iter()
+        .map(|(layer, forms)| {
+            let unknown = unknown.remove(&layer);
+            let layer = Layer::select(layers, &[layer])?
+                .pop()
+                .expect("No layer selected");
+            Lexicon::new(layer, forms, unknown)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::Lexicon;
+    use crate::layer::Layer;
+
+    fn lexicon(unknown: Option<Vec<String>>) -> Lexicon {
+        let layer = Layer::select(&Layer::conllu(), &["upos".to_owned()])
+            .unwrap()
+            .pop()
+            .unwrap();
+
+        let mut forms = HashMap::new();
+        forms.insert(
+            "walk".to_owned(),
+            vec!["VERB".to_owned(), "NOUN".to_owned()],
+        );
+        forms.insert("Paris".to_owned(), vec!["PROPN".to_owned()]);
+
+        Lexicon::new(layer, forms, unknown).unwrap()
+    }
+
+    #[test]
+    fn labels_of_known_form() {
+        let lexicon = lexicon(None);
+        assert_eq!(
+            lexicon.labels("walk"),
+            Some(&["VERB".to_owned(), "NOUN".to_owned()][..])
+        );
+        assert_eq!(lexicon.labels("Paris"), Some(&["PROPN".to_owned()][..]));
+    }
+
+    #[test]
+    fn labels_of_capitalized_form() {
+        let lexicon = lexicon(None);
+        assert_eq!(
+            lexicon.labels("Walk"),
+            Some(&["VERB".to_owned(), "NOUN".to_owned()][..])
+        );
+        assert_eq!(lexicon.labels("paris"), None);
+    }
+
+    #[test]
+    fn labels_of_unknown_form() {
+        assert_eq!(lexicon(None).labels("run"), None);
+        assert_eq!(
+            lexicon(Some(vec!["X".to_owned()])).labels("run"),
+            Some(&["X".to_owned()][..])
+        );
+    }
+
+    #[test]
+    fn lexicon_rejects_empty_entries() {
+        let layer = Layer::conllu().remove(1);
+        let mut forms = HashMap::new();
+        forms.insert("walk".to_owned(), Vec::new());
+        assert!(Lexicon::new(layer, forms, None).is_err());
+    }
+
+    #[test]
+    fn lexicon_rejects_dependency_layer() {
+        let layer = Layer::conllu().pop().unwrap();
+        assert!(Lexicon::new(layer, HashMap::new(), None).is_err());
+    }
+}
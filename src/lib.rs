@@ -1,23 +1,230 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use pyo3::exceptions;
 use pyo3::prelude::*;
+use pyo3::wrap_pyfunction;
+
+mod archive;
+
+mod cache;
+
+mod columns;
+use columns::Columns;
 
 mod config;
 pub use config::{PyConfig, PyLabeler, PyModel};
 
+mod dicts;
+
+mod edit;
+
+mod evaluation;
+
 pub(crate) mod io;
 
+mod json;
+use json::JsonSentence;
+
+mod layer;
+use layer::Layer;
+
+mod length;
+
+mod lexicon;
+
+mod metadata;
+
+mod multiword;
+pub use multiword::{PyMultiwordToken, PyMultiwordTokens};
+
+mod registry;
+
 mod sentence;
-pub use sentence::{PySentence, PySentenceIterator, PyToken};
+pub use sentence::{PySentence, PySentenceIterator, PyToken, PyTokens};
+
+mod tree;
 
 mod annotator;
 pub use annotator::PyAnnotator;
 
+mod spacy;
+pub use spacy::PySpacyComponent;
+
+mod tagger;
+
+/// dump_json(sentences, path)
+/// --
+///
+/// Write sentences to a file in the JSON Lines format. Every line
+/// contains a sentence, serialized as by `Sentence.to_json`.
+///
+/// Parameters
+/// ----------
+/// sentences : list
+///     List of Sentence objects to write.
+/// path : str
+///     Path of the output file.
+#[pyfunction]
+fn dump_json(sentences: Vec<PyRef<PySentence>>, path: &str) -> PyResult<()> {
+    let io_err = |err: std::io::Error| {
+        exceptions::PyIOError::new_err(format!(
+            "cannot write sentences to {}: {}",
+            path,
+            err.to_string()
+        ))
+    };
+
+    let mut writer = BufWriter::new(File::create(path).map_err(io_err)?);
+
+    for sentence in sentences {
+        serde_json::to_writer(
+            &mut writer,
+            &JsonSentence::new(&sentence.inner(), &sentence.multiword_tokens()),
+        )
+        .map_err(|err| {
+            exceptions::PyIOError::new_err(format!(
+                "cannot write sentences to {}: {}",
+                path,
+                err.to_string()
+            ))
+        })?;
+        writeln!(writer).map_err(io_err)?;
+    }
+
+    writer.flush().map_err(io_err)
+}
+
+/// evaluate(gold_sentences, predicted_sentences, layers=None, config=None)
+/// --
+///
+/// Evaluate predicted sentences against gold-standard sentences. The
+/// forms of the gold-standard and predicted sentences must be
+/// identical.
+///
+/// The evaluation is returned as a dictionary, with an entry for each
+/// evaluated layer. The entry of a layer contains the `accuracy`, the
+/// precision, recall, F1 score and support of every label (`labels`)
+/// and the `confusion` matrix as a dictionary of gold labels to
+/// dictionaries of predicted labels to counts. The entry of a
+/// dependency layer additionally contains the unlabeled (`uas`) and
+/// labeled (`las`) attachment scores. Tokens without an annotation
+/// have the label `_`.
+///
+/// If the configuration of the model that predicted the sentences is
+/// given, the layers are those of the model's encoders, named after
+/// the encoders. Otherwise, the CoNLL-U layers `lemma`, `upos`,
+/// `xpos`, `feats`, and `deprel` are used.
+///
+/// Parameters
+/// ----------
+/// gold_sentences : list
+///     List of gold-standard Sentence objects.
+/// predicted_sentences : list
+///     List of predicted Sentence objects.
+/// layers : list
+///     Names of the layers to evaluate. By default, all layers that are
+///     annotated in the predicted sentences are evaluated.
+/// config : Config
+///     Configuration of the model that predicted the sentences.
+#[pyfunction(layers = "None", config = "None")]
+fn evaluate(
+    py: Python,
+    gold_sentences: Vec<PyRef<PySentence>>,
+    predicted_sentences: Vec<PyRef<PySentence>>,
+    layers: Option<Vec<String>>,
+    config: Option<&PyConfig>,
+) -> PyResult<PyObject> {
+    let gold_sentences = gold_sentences
+        .iter()
+        .map(|sentence| sentence.inner())
+        .collect::<Vec<_>>();
+    let gold_sentences = gold_sentences.iter().map(|s| &**s).collect::<Vec<_>>();
+    let predicted_sentences = predicted_sentences
+        .iter()
+        .map(|sentence| sentence.inner())
+        .collect::<Vec<_>>();
+    let predicted_sentences = predicted_sentences.iter().map(|s| &**s).collect::<Vec<_>>();
+
+    let available = match config {
+        Some(config) => Layer::from_encoders(&config.as_ref().labeler.encoders),
+        None => Layer::conllu(),
+    };
+
+    let layers = match layers {
+        Some(layers) => Layer::select(&available, &layers)?,
+        None => evaluation::annotated_layers(&predicted_sentences, &available),
+    };
+
+    evaluation::evaluate(py, &gold_sentences, &predicted_sentences, &layers)
+}
+
+/// package_model(config, out_path)
+/// --
+///
+/// Package the files of a model into a single archive, which can be
+/// loaded with `Annotator.from_archive`. The archive is a zip file if
+/// `out_path` has the extension `.zip`, otherwise it is a tar file.
+///
+/// Parameters
+/// ----------
+/// config : Config
+///     Configuration of the model to package.
+/// out_path : str
+///     Path of the archive.
+#[pyfunction]
+fn package_model(config: &PyConfig, out_path: &str) -> PyResult<()> {
+    archive::package(&config.as_ref(), config.toml(), config.metadata(), out_path).map_err(|err| {
+        exceptions::PyIOError::new_err(format!("cannot package model: {}", err.to_string()))
+    })
+}
+
+/// sentences_to_columns(sentences)
+/// --
+///
+/// Convert a list of sentences to columns. The columns are returned
+/// as a dictionary of lists, with the keys `sentence`, `id`, `form`,
+/// `lemma`, `upos`, `xpos`, `feats`, `head`, and `deprel`. The
+/// `sentence` column contains the index of the sentence in the list.
+/// The root tokens are not included.
+///
+/// The dictionary can be used directly to construct a pandas
+/// `DataFrame`.
+///
+/// Parameters
+/// ----------
+/// sentences : list
+///     List of Sentence objects to convert.
+#[pyfunction]
+fn sentences_to_columns(py: Python, sentences: Vec<PyRef<PySentence>>) -> PyResult<PyObject> {
+    let mut columns = Columns::default();
+
+    for (sentence_idx, sentence) in sentences.iter().enumerate() {
+        columns.push_sentence(sentence_idx, &sentence.inner());
+    }
+
+    columns.into_dict(py, true)
+}
+
 #[pymodule]
-fn sticker2(_py: Python, m: &PyModule) -> PyResult<()> {
+fn sticker2(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<PyAnnotator>()?;
     m.add_class::<PyConfig>()?;
     m.add_class::<PyLabeler>()?;
     m.add_class::<PyModel>()?;
     m.add_class::<PySentence>()?;
+    m.add_function(wrap_pyfunction!(dump_json, m)?)?;
+    m.add_function(wrap_pyfunction!(evaluate, m)?)?;
+    m.add_function(wrap_pyfunction!(package_model, m)?)?;
+    m.add_function(wrap_pyfunction!(sentences_to_columns, m)?)?;
+
+    // Register the submodule, so that it can be imported as
+    // `sticker2.registry`.
+    let registry = registry::module(py)?;
+    py.import("sys")?
+        .getattr("modules")?
+        .set_item("sticker2.registry", registry)?;
+    m.add("registry", registry)?;
 
     Ok(())
 }
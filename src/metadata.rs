@@ -0,0 +1,199 @@
+//! Model metadata.
+//!
+//! Metadata is read from the optional `[metadata]` table of the
+//! configuration file. sticker2 rejects unknown tables, so the table
+//! is removed before the configuration is read by sticker2:
+//!
+//! ```toml
+//! [metadata]
+//! name = "de-ud"
+//! version = "3.0.0"
+//! language = "de"
+//! treebank = "UD_German-HDT"
+//! date = "2020-09-01"
+//!
+//! [metadata.checksums]
+//! labels = "<SHA-256 of the label file>"
+//! parameters = "<SHA-256 of the parameter file>"
+//! pretrain_config = "<SHA-256 of the pretraining configuration>"
+//! vocab = "<SHA-256 of the tokenizer vocabulary>"
+//! ```
+//!
+//! All fields are optional.
+
+use std::fs::File;
+use std::io::Read;
+
+use anyhow::{bail, Context, Result};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sticker2::config::{Config, Tokenizer};
+
+/// Model metadata.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Metadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub treebank: Option<String>,
+
+    /// Training date.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Checksums::is_empty")]
+    pub checksums: Checksums,
+}
+
+impl Metadata {
+    /// Remove the metadata table from a configuration.
+    ///
+    /// Returns the default (empty) metadata if the configuration does
+    /// not have a metadata table.
+    pub fn take_from_config(config: &mut toml::Value) -> Result<Self> {
+        match config
+            .as_table_mut()
+            .and_then(|table| table.remove("metadata"))
+        {
+            Some(metadata) => metadata.try_into().context("Cannot read model metadata"),
+            None => Ok(Metadata::default()),
+        }
+    }
+
+    /// Convert the metadata to a Python dictionary.
+    pub fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<&'py PyDict> {
+        let dict = PyDict::new(py);
+        dict.set_item("name", &self.name)?;
+        dict.set_item("version", &self.version)?;
+        dict.set_item("language", &self.language)?;
+        dict.set_item("treebank", &self.treebank)?;
+        dict.set_item("date", &self.date)?;
+
+        let checksums = PyDict::new(py);
+        for (file, checksum) in self.checksums.iter() {
+            checksums.set_item(file, checksum)?;
+        }
+        dict.set_item("checksums", checksums)?;
+
+        Ok(dict)
+    }
+}
+
+/// SHA-256 checksums of the files of a model.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Checksums {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pretrain_config: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vocab: Option<String>,
+}
+
+impl Checksums {
+    /// Compute the checksums of the files of a model.
+    pub fn compute(config: &Config) -> Result<Self> {
+        let [labels, parameters, pretrain_config, vocab] = model_files(config);
+
+        Ok(Checksums {
+            labels: Some(sha256(labels)?),
+            parameters: Some(sha256(parameters)?),
+            pretrain_config: Some(sha256(pretrain_config)?),
+            vocab: Some(sha256(vocab)?),
+        })
+    }
+
+    /// Verify the files of a model against the checksums.
+    ///
+    /// Files without a checksum are not verified.
+    pub fn verify(&self, config: &Config) -> Result<()> {
+        let files = model_files(config);
+
+        for ((file, expected), path) in self.iter().zip(files.iter()) {
+            let expected = match expected {
+                Some(expected) => expected,
+                None => continue,
+            };
+
+            let checksum = sha256(path)?;
+            if !checksum.eq_ignore_ascii_case(expected) {
+                bail!(
+                    "SHA-256 checksum of {} file {} is {}, expected {}",
+                    file,
+                    path,
+                    checksum,
+                    expected
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.iter().all(|(_, checksum)| checksum.is_none())
+    }
+
+    /// Iterate over the files and their checksums.
+    ///
+    /// The files are in the same order as `model_files`.
+    fn iter(&self) -> impl Iterator<Item = (&'static str, &Option<String>)> {
+        vec![
+            ("labels", &self.labels),
+            ("parameters", &self.parameters),
+            ("pretrain_config", &self.pretrain_config),
+            ("vocab", &self.vocab),
+        ]
+        .into_iter()
+    }
+}
+
+/// Get the label, parameter, pretraining configuration, and vocabulary
+/// files of a model.
+pub(crate) fn model_files(config: &Config) -> [&str; 4] {
+    let vocab = match &config.input.tokenizer {
+        Tokenizer::Albert { vocab }
+        | Tokenizer::Bert { vocab }
+        | Tokenizer::XlmRoberta { vocab } => vocab,
+    };
+
+    [
+        config.labeler.labels.as_str(),
+        config.model.parameters.as_str(),
+        config.model.pretrain_config.as_str(),
+        vocab.as_str(),
+    ]
+}
+
+/// Compute the SHA-256 checksum of a file as a hexadecimal string.
+pub(crate) fn sha256(path: &str) -> Result<String> {
+    let mut f = File::open(path).context(format!("Cannot open file: {}", path))?;
+
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        let n = f
+            .read(&mut buf)
+            .context(format!("Cannot read file: {}", path))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
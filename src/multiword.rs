@@ -0,0 +1,295 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+
+use conllu::graph::Sentence;
+use pyo3::class::basic::PyObjectProtocol;
+use pyo3::class::sequence::PySequenceProtocol;
+use pyo3::exceptions;
+use pyo3::prelude::*;
+
+/// A multiword token.
+///
+/// A multiword token spans the (syntactic) tokens `first..=last` and
+/// has its own surface form, such as German *zum* for *zu dem*.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MultiwordToken {
+    first: usize,
+    last: usize,
+    form: String,
+}
+
+impl MultiwordToken {
+    pub fn new(first: usize, last: usize, form: impl Into<String>) -> Self {
+        MultiwordToken {
+            first,
+            last,
+            form: form.into(),
+        }
+    }
+
+    /// The first token of the range.
+    pub fn first(&self) -> usize {
+        self.first
+    }
+
+    /// The last token of the range (inclusive).
+    pub fn last(&self) -> usize {
+        self.last
+    }
+
+    /// The surface form of the multiword token.
+    pub fn form(&self) -> &str {
+        &self.form
+    }
+
+    /// Check whether this multiword token overlaps with another one.
+    fn overlaps(&self, other: &MultiwordToken) -> bool {
+        self.first <= other.last && other.first <= self.last
+    }
+}
+
+/// Add a multiword token to a list of sorted multiword tokens.
+///
+/// Returns an error if the range is not valid for the sentence or if
+/// it overlaps with an existing multiword token.
+pub fn add_multiword_token(
+    sentence: &Sentence,
+    multiword_tokens: &mut Vec<MultiwordToken>,
+    multiword_token: MultiwordToken,
+) -> PyResult<()> {
+    if multiword_token.first == 0 {
+        return Err(exceptions::PyValueError::new_err(
+            "multiword token cannot include the root node",
+        ));
+    }
+
+    if multiword_token.first >= multiword_token.last {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "multiword token must span at least two tokens, got range: {}-{}",
+            multiword_token.first, multiword_token.last
+        )));
+    }
+
+    if multiword_token.last >= sentence.len() {
+        return Err(exceptions::PyIndexError::new_err(format!(
+            "multiword token range out of range: {}-{}",
+            multiword_token.first, multiword_token.last
+        )));
+    }
+
+    if let Some(other) = multiword_tokens
+        .iter()
+        .find(|other| other.overlaps(&multiword_token))
+    {
+        return Err(exceptions::PyValueError::new_err(format!(
+            "multiword token {}-{} overlaps with {}-{}",
+            multiword_token.first, multiword_token.last, other.first, other.last
+        )));
+    }
+
+    let idx = multiword_tokens
+        .iter()
+        .position(|other| other.first > multiword_token.first)
+        .unwrap_or(multiword_tokens.len());
+    multiword_tokens.insert(idx, multiword_token);
+
+    Ok(())
+}
+
+/// Renumber multiword tokens after a change of the token sequence.
+///
+/// The first and last token of each range are renumbered using
+/// `map_first` and `map_last`. Ranges that span less than two tokens
+/// after renumbering are removed.
+pub fn renumber_multiword_tokens(
+    multiword_tokens: &mut Vec<MultiwordToken>,
+    map_first: impl Fn(usize) -> usize,
+    map_last: impl Fn(usize) -> usize,
+) {
+    for mwt in multiword_tokens.iter_mut() {
+        mwt.first = map_first(mwt.first);
+        mwt.last = map_last(mwt.last);
+    }
+
+    multiword_tokens.retain(|mwt| mwt.first < mwt.last);
+}
+
+/// Write a sentence in CoNLL-U format, including multiword token lines.
+pub fn to_conllu(sentence: &Sentence, multiword_tokens: &[MultiwordToken]) -> String {
+    let sentence_str = format!("{}", sentence);
+
+    if multiword_tokens.is_empty() {
+        return sentence_str;
+    }
+
+    let mut lines = Vec::new();
+    for line in sentence_str.lines() {
+        let id = line
+            .split('\t')
+            .next()
+            .and_then(|id| id.parse::<usize>().ok());
+
+        if let Some(id) = id {
+            if let Some(mwt) = multiword_tokens.iter().find(|mwt| mwt.first == id) {
+                lines.push(format!(
+                    "{}-{}\t{}\t_\t_\t_\t_\t_\t_\t_\t_",
+                    mwt.first, mwt.last, mwt.form
+                ));
+            }
+        }
+
+        lines.push(line.to_owned());
+    }
+
+    let mut conllu = lines.join("\n");
+    if sentence_str.ends_with('\n') {
+        conllu.push('\n');
+    }
+
+    conllu
+}
+
+/// Multiword token spanning the tokens `first` to `last` (inclusive).
+#[pyclass(name=MultiwordToken)]
+pub struct PyMultiwordToken {
+    inner: MultiwordToken,
+}
+
+#[pymethods]
+impl PyMultiwordToken {
+    /// Get the first token of the range.
+    #[getter]
+    fn get_first(&self) -> usize {
+        self.inner.first
+    }
+
+    /// Get the surface form.
+    #[getter]
+    fn get_form(&self) -> String {
+        self.inner.form.clone()
+    }
+
+    /// Get the last token of the range (inclusive).
+    #[getter]
+    fn get_last(&self) -> usize {
+        self.inner.last
+    }
+}
+
+#[pyproto]
+impl PyObjectProtocol for PyMultiwordToken {
+    fn __repr__(&self) -> PyResult<String> {
+        Ok(format!(
+            "MultiwordToken(first = {}, last = {}, form = '{}')",
+            self.inner.first, self.inner.last, self.inner.form
+        ))
+    }
+}
+
+/// Multiword tokens of a sentence.
+#[pyclass(name=MultiwordTokens,unsendable)]
+pub struct PyMultiwordTokens {
+    sent: Rc<RefCell<Sentence>>,
+    multiword_tokens: Rc<RefCell<Vec<MultiwordToken>>>,
+}
+
+impl PyMultiwordTokens {
+    pub(crate) fn new(
+        sent: Rc<RefCell<Sentence>>,
+        multiword_tokens: Rc<RefCell<Vec<MultiwordToken>>>,
+    ) -> Self {
+        PyMultiwordTokens {
+            sent,
+            multiword_tokens,
+        }
+    }
+
+    fn inner(&self) -> Ref<Vec<MultiwordToken>> {
+        self.multiword_tokens.borrow()
+    }
+
+    fn inner_mut(&self) -> RefMut<Vec<MultiwordToken>> {
+        self.multiword_tokens.borrow_mut()
+    }
+}
+
+#[pymethods]
+impl PyMultiwordTokens {
+    /// add(first, last, form)
+    /// --
+    ///
+    /// Add a multiword token spanning the tokens `first` to `last`
+    /// (inclusive). A `ValueError` is raised when the range overlaps
+    /// with an existing multiword token.
+    fn add(&self, first: usize, last: usize, form: String) -> PyResult<()> {
+        add_multiword_token(
+            &self.sent.borrow(),
+            &mut self.inner_mut(),
+            MultiwordToken::new(first, last, form),
+        )
+    }
+
+    /// clear()
+    /// --
+    ///
+    /// Remove all multiword tokens.
+    fn clear(&self) {
+        self.inner_mut().clear()
+    }
+
+    /// remove(first)
+    /// --
+    ///
+    /// Remove the multiword token that starts at token `first`.
+    fn remove(&self, first: usize) -> PyResult<()> {
+        let mut multiword_tokens = self.inner_mut();
+
+        let idx = multiword_tokens
+            .iter()
+            .position(|mwt| mwt.first == first)
+            .ok_or_else(|| {
+                exceptions::PyKeyError::new_err(format!(
+                    "no multiword token starts at token: {}",
+                    first
+                ))
+            })?;
+
+        multiword_tokens.remove(idx);
+
+        Ok(())
+    }
+}
+
+#[pyproto]
+impl PyObjectProtocol for PyMultiwordTokens {
+    fn __repr__(&self) -> PyResult<String> {
+        let mwt_reprs = self
+            .inner()
+            .iter()
+            .map(|mwt| PyMultiwordToken { inner: mwt.clone() }.__repr__())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(format!("MultiwordTokens([{}])", mwt_reprs.join(", ")))
+    }
+}
+
+#[pyproto]
+impl PySequenceProtocol for PyMultiwordTokens {
+    fn __len__(&self) -> PyResult<usize> {
+        Ok(self.inner().len())
+    }
+
+    fn __getitem__(&self, idx: isize) -> PyResult<PyMultiwordToken> {
+        let multiword_tokens = self.inner();
+
+        if idx >= multiword_tokens.len() as isize || idx < 0 {
+            Err(exceptions::PyIndexError::new_err(
+                "multiword token index out of range",
+            ))
+        } else {
+            Ok(PyMultiwordToken {
+                inner: multiword_tokens[idx as usize].clone(),
+            })
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Registry of local models.
+//!
+//! The registry indexes the model directories under a root directory.
+//! Every subdirectory with a `sticker2.conf` configuration file is a
+//! model, named after the directory. Other entries of the root are
+//! ignored. The root is given by the `STICKER2_MODELS` environment
+//! variable. The language and version of a model are read from the
+//! `[metadata]` table of its configuration (see the `metadata`
+//! module); they are not used to name or select models.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use pyo3::wrap_pyfunction;
+
+use crate::PyConfig;
+
+/// Environment variable with the registry root.
+pub const ROOT_ENV: &str = "STICKER2_MODELS";
+
+/// Name of the configuration file in a model directory.
+const CONFIG_NAME: &str = "sticker2.conf";
+
+/// Documentation of the Python module.
+const MODULE_DOC: &str = "Registry of local sticker2 models.
+
+The registry root is a directory with a subdirectory per model. Every
+subdirectory that contains a sticker2.conf configuration file is a model,
+named after the subdirectory. The root is given by the STICKER2_MODELS
+environment variable or the root argument of the registry functions.";
+
+/// Get the registry root.
+fn registry_root(root: Option<&str>) -> PyResult<PathBuf> {
+    match root {
+        Some(root) => Ok(PathBuf::from(root)),
+        None => env::var_os(ROOT_ENV).map(PathBuf::from).ok_or_else(|| {
+            exceptions::PyValueError::new_err(format!(
+                "no registry root given and {} is not set",
+                ROOT_ENV
+            ))
+        }),
+    }
+}
+
+/// Get the configuration path of a model in the registry.
+pub fn config_path(name: &str, root: Option<&str>) -> PyResult<String> {
+    let root = registry_root(root)?;
+
+    let path = model_names(&root)?
+        .into_iter()
+        .find(|model_name| model_name == name)
+        .map(|name| root.join(name).join(CONFIG_NAME))
+        .ok_or_else(|| {
+            exceptions::PyKeyError::new_err(format!(
+                "unknown model '{}' in registry: {}",
+                name,
+                root.display()
+            ))
+        })?;
+
+    path.to_str()
+        .map(ToOwned::to_owned)
+        .ok_or_else(|| exceptions::PyValueError::new_err("model path is not valid Unicode"))
+}
+
+/// Get the names of the models under the registry root.
+fn model_names(root: &Path) -> PyResult<Vec<String>> {
+    let entries = fs::read_dir(root).map_err(|err| {
+        exceptions::PyIOError::new_err(format!(
+            "cannot read registry root {}: {}",
+            root.display(),
+            err.to_string()
+        ))
+    })?;
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|err| exceptions::PyIOError::new_err(err.to_string()))?;
+        if !entry.path().join(CONFIG_NAME).is_file() {
+            continue;
+        }
+
+        if let Some(name) = entry.file_name().to_str() {
+            names.push(name.to_owned());
+        }
+    }
+
+    names.sort();
+
+    Ok(names)
+}
+
+/// list_models(root=None)
+/// --
+///
+/// List the models in the registry. Returns a list of dictionaries
+/// with the keys `name`, `config` (the configuration path), `language`,
+/// `version`, and `encoders` (the names of the model's encoders).
+/// Models whose configuration cannot be read are skipped with a
+/// warning.
+///
+/// Parameters
+/// ----------
+/// root : str
+///     Registry root. Default: the `STICKER2_MODELS` environment variable.
+#[pyfunction(root = "None")]
+fn list_models(py: Python, root: Option<&str>) -> PyResult<Vec<PyObject>> {
+    let root = registry_root(root)?;
+
+    let mut models = Vec::new();
+    for name in model_names(&root)? {
+        let path = root.join(&name).join(CONFIG_NAME);
+        let path = path
+            .to_str()
+            .ok_or_else(|| exceptions::PyValueError::new_err("model path is not valid Unicode"))?;
+
+        let config = match PyConfig::from_file(path) {
+            Ok(config) => config,
+            Err(err) => {
+                PyErr::warn(
+                    py,
+                    py.get_type::<exceptions::PyUserWarning>(),
+                    &format!("skipping model '{}': {}", name, err),
+                    0,
+                )?;
+                continue;
+            }
+        };
+        let metadata = config.metadata();
+
+        let encoders = config
+            .as_ref()
+            .labeler
+            .encoders
+            .iter()
+            .map(|encoder| encoder.name.clone())
+            .collect::<Vec<_>>();
+
+        let dict = PyDict::new(py);
+        dict.set_item("name", name)?;
+        dict.set_item("config", path)?;
+        dict.set_item("language", &metadata.language)?;
+        dict.set_item("version", &metadata.version)?;
+        dict.set_item("encoders", encoders)?;
+
+        models.push(dict.into());
+    }
+
+    Ok(models)
+}
+
+/// model_config(name, root=None)
+/// --
+///
+/// Get the configuration path of a model in the registry.
+///
+/// Parameters
+/// ----------
+/// name : str
+///     Name of the model.
+/// root : str
+///     Registry root. Default: the `STICKER2_MODELS` environment variable.
+#[pyfunction(root = "None")]
+fn model_config(name: &str, root: Option<&str>) -> PyResult<String> {
+    config_path(name, root)
+}
+
+/// Construct the `sticker2.registry` module.
+pub fn module(py: Python) -> PyResult<&PyModule> {
+    let m = PyModule::new(py, "registry")?;
+    m.add("__doc__", MODULE_DOC)?;
+    m.add_function(wrap_pyfunction!(list_models, m)?)?;
+    m.add_function(wrap_pyfunction!(model_config, m)?)?;
+    Ok(m)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::path::Path;
+
+    use super::{config_path, model_names, CONFIG_NAME};
+
+    /// Construct a registry with two models and entries that are not
+    /// models.
+    fn registry() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        for model in &["en-ud", "de-ud"] {
+            fs::create_dir(root.path().join(model)).unwrap();
+            File::create(root.path().join(model).join(CONFIG_NAME)).unwrap();
+        }
+
+        fs::create_dir(root.path().join("not-a-model")).unwrap();
+        File::create(root.path().join("not-a-model").join("sticker.conf")).unwrap();
+        File::create(root.path().join(CONFIG_NAME)).unwrap();
+
+        root
+    }
+
+    #[test]
+    fn names_of_models() {
+        let root = registry();
+        assert_eq!(
+            model_names(root.path()).unwrap(),
+            vec!["de-ud".to_owned(), "en-ud".to_owned()]
+        );
+    }
+
+    #[test]
+    fn config_path_of_model() {
+        let root = registry();
+        let path = config_path("de-ud", root.path().to_str()).unwrap();
+        assert_eq!(
+            Path::new(&path),
+            root.path().join("de-ud").join(CONFIG_NAME)
+        );
+    }
+
+    #[test]
+    fn unknown_model_is_rejected() {
+        let root = registry();
+        assert!(config_path("fr-ud", root.path().to_str()).is_err());
+        assert!(config_path("not-a-model", root.path().to_str()).is_err());
+    }
+
+    #[test]
+    fn missing_root_is_rejected() {
+        let root = registry();
+        let missing = root.path().join("missing");
+        assert!(model_names(&missing).is_err());
+        assert!(config_path("de-ud", missing.to_str()).is_err());
+    }
+}
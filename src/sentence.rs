@@ -1,19 +1,36 @@
-use std::cell::{Ref, RefCell, RefMut};
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::os::raw::c_long;
 use std::rc::Rc;
 
 use conllu::graph::{Node, Sentence};
 use conllu::token::Token;
-use pyo3::class::basic::PyObjectProtocol;
+use pyo3::class::basic::{CompareOp, PyObjectProtocol};
 use pyo3::class::iter::PyIterProtocol;
 use pyo3::class::mapping::PyMappingProtocol;
 use pyo3::class::sequence::PySequenceProtocol;
 use pyo3::exceptions;
 use pyo3::prelude::*;
+use pyo3::types::{PyDict, PySlice};
+
+use crate::columns::Columns;
+use crate::dicts;
+use crate::edit;
+use crate::json::JsonSentence;
+use crate::multiword::{to_conllu, MultiwordToken, PyMultiwordTokens};
+use crate::spacy;
 
 /// Sentence that can be annotated.
+///
+/// Tokens obtained from a sentence refer to the sentence. After
+/// `insert`, `delete`, `merge_tokens`, or `split_token`, tokens that
+/// were obtained before the edit raise an `IndexError` when used.
 #[pyclass(name=Sentence,unsendable)]
 pub struct PySentence {
     inner: Rc<RefCell<Sentence>>,
+    multiword_tokens: Rc<RefCell<Vec<MultiwordToken>>>,
+
+    /// Generation of the sentence, see `HandleGeneration`.
+    generation: Rc<Cell<usize>>,
 }
 
 #[pymethods]
@@ -27,13 +44,230 @@ impl PySentence {
     fn __new__(forms: Vec<&str>) -> Self {
         let sent = forms.into_iter().map(Token::new).collect::<Sentence>();
 
-        PySentence {
-            inner: Rc::new(sent.into()),
+        sent.into()
+    }
+
+    /// copy()
+    /// --
+    ///
+    /// Return a copy of the sentence. Changes to the copy do not
+    /// affect the original sentence.
+    fn copy(&self) -> PySentence {
+        PySentence::new(self.inner().clone(), self.multiword_tokens().clone())
+    }
+
+    /// delete(idx)
+    /// --
+    ///
+    /// Delete the token at the given index. Dependency relations are
+    /// renumbered. A `ValueError` is raised when the token has
+    /// dependents.
+    fn delete(&self, idx: usize) -> PyResult<()> {
+        self.edit(|sentence, multiword_tokens| edit::delete(sentence, multiword_tokens, idx))
+    }
+
+    /// insert(idx, form)
+    /// --
+    ///
+    /// Insert a token with the given form before the token at `idx`.
+    /// Dependency relations are renumbered, the new token does not
+    /// have a head. Raises `ValueError` when the token would be
+    /// inserted inside a multiword token.
+    fn insert(&self, idx: usize, form: &str) -> PyResult<()> {
+        self.edit(|sentence, multiword_tokens| edit::insert(sentence, multiword_tokens, idx, form))
+    }
+
+    /// from_dicts(dicts)
+    /// --
+    ///
+    /// Construct a sentence from a list of token dictionaries, as used
+    /// by Stanza. Every token is a dictionary with the keys `id`,
+    /// `text`, `lemma`, `upos`, `xpos`, `feats`, `head`, `deprel`, and
+    /// `misc`. Only `id` and `text` are required. Multiword tokens are
+    /// dictionaries with the keys `id` and `text`, where `id` is a
+    /// tuple of the first and last token of the range.
+    #[staticmethod]
+    fn from_dicts(dicts: Vec<&PyDict>) -> PyResult<PySentence> {
+        let (sentence, multiword_tokens) = dicts::from_dicts(dicts)?;
+        Ok(PySentence::new(sentence, multiword_tokens))
+    }
+
+    /// from_json(json)
+    /// --
+    ///
+    /// Construct a sentence from its JSON serialization. See `to_json`
+    /// for a description of the format.
+    #[staticmethod]
+    fn from_json(json: &str) -> PyResult<PySentence> {
+        let json_sentence: JsonSentence = serde_json::from_str(json).map_err(|err| {
+            exceptions::PyValueError::new_err(format!(
+                "cannot deserialize sentence: {}",
+                err.to_string()
+            ))
+        })?;
+
+        let (sentence, multiword_tokens) = json_sentence.into_sentence()?;
+        Ok(PySentence::new(sentence, multiword_tokens))
+    }
+
+    /// from_spacy(doc_or_span)
+    /// --
+    ///
+    /// Construct a sentence from a spaCy `Doc` or `Span`. Tokens that
+    /// are not followed by whitespace get the `SpaceAfter=No` misc
+    /// feature. Tokens of a span that are attached to a head outside
+    /// the span are attached to the root.
+    #[staticmethod]
+    fn from_spacy(doc_or_span: &PyAny) -> PyResult<PySentence> {
+        spacy::from_spacy(doc_or_span).map(Into::into)
+    }
+
+    /// iter(include_root=True)
+    /// --
+    ///
+    /// Iterate over the tokens of the sentence. The artificial root
+    /// token is only returned when `include_root` is true.
+    #[args(include_root = "true")]
+    fn iter(&self, include_root: bool) -> PySentenceIterator {
+        PySentenceIterator {
+            sent: self.inner.clone(),
+            generation: self.generation.clone(),
+            idx: if include_root { 0 } else { 1 },
         }
     }
+
+    /// merge_tokens(first, last, form)
+    /// --
+    ///
+    /// Merge the tokens `first` to `last` (inclusive) into a single
+    /// token with the given form. The merged token is attached to
+    /// the head of the range and receives the dependents of the merged
+    /// tokens. A `ValueError` is raised when more than one token in
+    /// the range has a head outside the range.
+    fn merge_tokens(&self, first: usize, last: usize, form: &str) -> PyResult<()> {
+        self.edit(|sentence, multiword_tokens| {
+            edit::merge_tokens(sentence, multiword_tokens, first, last, form)
+        })
+    }
+
+    /// split_token(idx, forms, multiword=False)
+    /// --
+    ///
+    /// Split the token at `idx` into tokens with the given forms. The
+    /// first new token takes over the head and the dependents of the
+    /// split token. If `multiword` is true, a multiword token with the
+    /// form of the split token is added for the new tokens.
+    #[args(multiword = "false")]
+    fn split_token(&self, idx: usize, forms: Vec<String>, multiword: bool) -> PyResult<()> {
+        self.edit(|sentence, multiword_tokens| {
+            edit::split_token(sentence, multiword_tokens, idx, &forms, multiword)
+        })
+    }
+
+    /// Get the multiword tokens of the sentence.
+    #[getter]
+    fn get_multiword_tokens(&self) -> PyMultiwordTokens {
+        PyMultiwordTokens::new(self.inner.clone(), self.multiword_tokens.clone())
+    }
+
+    /// to_columns()
+    /// --
+    ///
+    /// Convert the sentence to columns. The columns are returned as a
+    /// dictionary of lists, with the keys `id`, `form`, `lemma`,
+    /// `upos`, `xpos`, `feats`, `head`, and `deprel`. The root token
+    /// is not included.
+    fn to_columns(&self, py: Python) -> PyResult<PyObject> {
+        let mut columns = Columns::default();
+        columns.push_sentence(0, &self.inner());
+        columns.into_dict(py, false)
+    }
+
+    /// to_dicts()
+    /// --
+    ///
+    /// Convert the sentence to a list of token dictionaries, as used by
+    /// Stanza. See `from_dicts` for a description of the format. The
+    /// root token is not included.
+    fn to_dicts(&self, py: Python) -> PyResult<Vec<PyObject>> {
+        dicts::to_dicts(py, &self.inner(), &self.multiword_tokens())
+    }
+
+    /// to_json()
+    /// --
+    ///
+    /// Serialize the sentence to JSON. The sentence is serialized as an
+    /// object with the keys:
+    ///
+    /// * `comments`: list of comments. Attribute-value comments are
+    ///   objects with the keys `attr` and `val`, other comments are
+    ///   strings.
+    /// * `tokens`: list of tokens, excluding the root. Every token is
+    ///   an object with the keys `id`, `form`, `lemma`, `upos`, `xpos`,
+    ///   `features`, `misc`, `head`, and `deprel`. `features` and
+    ///   `misc` are objects mapping feature names to values. `head` and
+    ///   `deprel` encode the dependency relation of the token. Keys of
+    ///   fields without a value are omitted.
+    /// * `multiword_tokens`: list of multiword tokens, objects with the
+    ///   keys `first`, `last`, and `form`.
+    fn to_json(&self) -> PyResult<String> {
+        serde_json::to_string(&JsonSentence::new(&self.inner(), &self.multiword_tokens())).map_err(
+            |err| {
+                exceptions::PyValueError::new_err(format!(
+                    "cannot serialize sentence: {}",
+                    err.to_string()
+                ))
+            },
+        )
+    }
+
+    /// to_spacy(vocab)
+    /// --
+    ///
+    /// Convert the sentence to a spaCy `Doc`. Forms, lemmas,
+    /// part-of-speech tags, morphological features and dependency
+    /// relations are transferred. Tokens with the `SpaceAfter=No`
+    /// misc feature are not followed by a space.
+    ///
+    /// Parameters
+    /// ----------
+    /// vocab : spacy.vocab.Vocab
+    ///     Vocabulary to construct the `Doc` with, usually `nlp.vocab`.
+    fn to_spacy(&self, py: Python, vocab: &PyAny) -> PyResult<PyObject> {
+        spacy::to_spacy(py, &self.inner(), vocab)
+    }
+
+    /// Get the tokens of the sentence, excluding the root.
+    ///
+    /// Tokens are indexed by their CoNLL-U identifier, so the first
+    /// token has index 1.
+    #[getter]
+    fn get_tokens(&self) -> PyTokens {
+        PyTokens {
+            sent: self.inner.clone(),
+            generation: self.generation.clone(),
+        }
+    }
+
+    fn __copy__(&self) -> PySentence {
+        self.copy()
+    }
+
+    fn __deepcopy__(&self, _memo: &PyAny) -> PySentence {
+        self.copy()
+    }
 }
 
 impl PySentence {
+    /// Construct a sentence with multiword tokens.
+    pub fn new(sentence: Sentence, multiword_tokens: Vec<MultiwordToken>) -> Self {
+        PySentence {
+            inner: Rc::new(RefCell::new(sentence)),
+            multiword_tokens: Rc::new(RefCell::new(multiword_tokens)),
+            generation: Rc::new(Cell::new(0)),
+        }
+    }
+
     pub fn inner(&self) -> Ref<Sentence> {
         self.inner.borrow()
     }
@@ -41,13 +275,38 @@ impl PySentence {
     pub fn inner_mut(&self) -> RefMut<Sentence> {
         self.inner.borrow_mut()
     }
+
+    pub fn multiword_tokens(&self) -> Ref<Vec<MultiwordToken>> {
+        self.multiword_tokens.borrow()
+    }
+
+    fn token(&self, token_idx: usize) -> PyToken {
+        PyToken {
+            sent: self.inner.clone(),
+            generation: HandleGeneration::new(&self.generation),
+            token_idx,
+        }
+    }
+
+    /// Apply an edit that renumbers the tokens of the sentence.
+    ///
+    /// Token handles that were created before the edit become stale.
+    fn edit<F>(&self, edit: F) -> PyResult<()>
+    where
+        F: FnOnce(&mut Sentence, &mut Vec<MultiwordToken>) -> PyResult<()>,
+    {
+        edit(
+            &mut self.inner_mut(),
+            &mut self.multiword_tokens.borrow_mut(),
+        )?;
+        self.generation.set(self.generation.get() + 1);
+        Ok(())
+    }
 }
 
 impl From<Sentence> for PySentence {
     fn from(sentence: Sentence) -> Self {
-        PySentence {
-            inner: Rc::new(RefCell::new(sentence)),
-        }
+        PySentence::new(sentence, Vec::new())
     }
 }
 
@@ -56,6 +315,7 @@ impl PyIterProtocol for PySentence {
     fn __iter__(slf: PyRefMut<Self>) -> PyResult<PySentenceIterator> {
         Ok(PySentenceIterator {
             sent: slf.inner.clone(),
+            generation: slf.generation.clone(),
             idx: 0,
         })
     }
@@ -65,20 +325,30 @@ impl PyIterProtocol for PySentence {
 impl PyObjectProtocol for PySentence {
     fn __repr__(&self) -> PyResult<String> {
         let token_reprs = (0..self.inner.borrow().len())
-            .map(|token_idx| {
-                PyToken {
-                    token_idx,
-                    sent: self.inner.clone(),
-                }
-                .__repr__()
-            })
+            .map(|token_idx| self.token(token_idx).__repr__())
             .collect::<Result<Vec<_>, _>>()?;
 
         Ok(format!("Sentence([{}])", token_reprs.join(", ")))
     }
 
+    fn __richcmp__(&self, other: PyRef<PySentence>, op: CompareOp) -> PyResult<PyObject> {
+        let py = other.py();
+
+        let eq = *self.inner() == *other.inner()
+            && *self.multiword_tokens() == *other.multiword_tokens();
+
+        match op {
+            CompareOp::Eq => Ok(eq.into_py(py)),
+            CompareOp::Ne => Ok((!eq).into_py(py)),
+            _ => Ok(py.NotImplemented()),
+        }
+    }
+
     fn __str__(&self) -> PyResult<String> {
-        Ok(format!("{}", self.inner.borrow()))
+        Ok(to_conllu(
+            &self.inner.borrow(),
+            &self.multiword_tokens.borrow(),
+        ))
     }
 }
 
@@ -87,17 +357,95 @@ impl PySequenceProtocol for PySentence {
     fn __len__(&self) -> PyResult<usize> {
         Ok(self.inner.borrow().len())
     }
+}
+
+#[pyproto]
+impl PyMappingProtocol for PySentence {
+    fn __getitem__(&self, idx: &PyAny) -> PyResult<PyObject> {
+        let py = idx.py();
+        let len = self.inner.borrow().len();
+
+        if let Ok(slice) = idx.downcast::<PySlice>() {
+            let indices = slice.indices(len as c_long)?;
+            let tokens = (0..indices.slicelength)
+                .map(|i| self.token((indices.start + i * indices.step) as usize))
+                .collect::<Vec<_>>();
+            return Ok(tokens.into_py(py));
+        }
+
+        let idx = idx.extract::<isize>()?;
+        let idx = if idx < 0 { idx + len as isize } else { idx };
+
+        if idx >= len as isize || idx < 0 {
+            Err(exceptions::PyIndexError::new_err(
+                "token index out of range",
+            ))
+        } else {
+            Ok(self.token(idx as usize).into_py(py))
+        }
+    }
+}
 
+/// Tokens of a sentence, excluding the root.
+///
+/// Tokens are indexed by their CoNLL-U identifier, which starts at 1.
+#[pyclass(name=Tokens,unsendable)]
+pub struct PyTokens {
+    sent: Rc<RefCell<Sentence>>,
+    generation: Rc<Cell<usize>>,
+}
+
+impl PyTokens {
+    fn token(&self, token_idx: usize) -> PyToken {
+        PyToken {
+            sent: self.sent.clone(),
+            generation: HandleGeneration::new(&self.generation),
+            token_idx,
+        }
+    }
+}
+
+#[pyproto]
+impl PyIterProtocol for PyTokens {
+    fn __iter__(slf: PyRefMut<Self>) -> PyResult<PySentenceIterator> {
+        Ok(PySentenceIterator {
+            sent: slf.sent.clone(),
+            generation: slf.generation.clone(),
+            idx: 1,
+        })
+    }
+}
+
+#[pyproto]
+impl PyObjectProtocol for PyTokens {
+    fn __repr__(&self) -> PyResult<String> {
+        let token_reprs = (1..self.sent.borrow().len())
+            .map(|token_idx| self.token(token_idx).__repr__())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(format!("Tokens([{}])", token_reprs.join(", ")))
+    }
+}
+
+#[pyproto]
+impl PySequenceProtocol for PyTokens {
+    fn __len__(&self) -> PyResult<usize> {
+        Ok(self.sent.borrow().len() - 1)
+    }
+}
+
+#[pyproto]
+impl PyMappingProtocol for PyTokens {
     fn __getitem__(&self, idx: isize) -> PyResult<PyToken> {
-        if idx >= self.inner.borrow().len() as isize || idx < 0 {
+        let len = self.sent.borrow().len() as isize;
+        let idx = if idx < 0 { idx + len } else { idx };
+
+        if idx >= len || idx < 1 {
             Err(exceptions::PyIndexError::new_err(
                 "token index out of range",
             ))
         } else {
-            Ok(PyToken {
-                sent: self.inner.clone(),
-                token_idx: idx as usize,
-            })
+            Ok(self.token(idx as usize))
         }
     }
 }
@@ -108,6 +456,7 @@ impl PySequenceProtocol for PySentence {
 #[pyclass(name=SentenceIterator,unsendable)]
 pub struct PySentenceIterator {
     sent: Rc<RefCell<Sentence>>,
+    generation: Rc<Cell<usize>>,
     idx: usize,
 }
 
@@ -123,6 +472,7 @@ impl PyIterProtocol for PySentenceIterator {
         if slf.idx < slf.sent.borrow().len() {
             let token = PyToken {
                 sent: slf.sent.clone(),
+                generation: HandleGeneration::new(&slf.generation),
                 token_idx: slf.idx,
             };
 
@@ -139,6 +489,7 @@ impl PyIterProtocol for PySentenceIterator {
 #[pyclass(name=Token,unsendable)]
 pub struct PyToken {
     sent: Rc<RefCell<Sentence>>,
+    generation: HandleGeneration,
     token_idx: usize,
 }
 
@@ -149,45 +500,55 @@ impl PyToken {
     fn get_features(&self) -> PyFeatures {
         PyFeatures {
             sent: self.sent.clone(),
+            generation: self.generation.clone(),
             token_idx: self.token_idx,
         }
     }
 
     /// Get the form of the token.
     #[getter]
-    fn get_form(&self) -> Option<String> {
-        match self.sent.borrow()[self.token_idx] {
-            Node::Token(ref token) => Some(token.form().to_owned()),
-            Node::Root => None,
+    fn get_form(&self) -> PyResult<Option<String>> {
+        match self.sentence()?[self.token_idx] {
+            Node::Token(ref token) => Ok(Some(token.form().to_owned())),
+            Node::Root => Ok(None),
         }
     }
 
     /// Get the (dependency) head.
     #[getter]
-    fn get_head(&self) -> Option<usize> {
-        self.sent
-            .borrow()
+    fn get_head(&self) -> PyResult<Option<usize>> {
+        Ok(self
+            .sentence()?
             .dep_graph()
             .head(self.token_idx)
-            .map(|triple| triple.head())
+            .map(|triple| triple.head()))
     }
 
     /// Get the (dependency) head relation.
     #[getter]
-    fn get_head_rel(&self) -> Option<String> {
-        self.sent
-            .borrow()
+    fn get_head_rel(&self) -> PyResult<Option<String>> {
+        Ok(self
+            .sentence()?
             .dep_graph()
             .head(self.token_idx)
-            .and_then(|triple| triple.relation().map(ToOwned::to_owned))
+            .and_then(|triple| triple.relation().map(ToOwned::to_owned)))
+    }
+
+    /// Get the identifier of the token.
+    ///
+    /// The identifier is the CoNLL-U token identifier, the root has
+    /// identifier 0.
+    #[getter]
+    fn get_id(&self) -> usize {
+        self.token_idx
     }
 
     /// Get the lemma.
     #[getter]
-    fn get_lemma(&self) -> Option<String> {
-        match self.sent.borrow()[self.token_idx] {
-            Node::Token(ref token) => token.lemma().map(ToOwned::to_owned),
-            Node::Root => None,
+    fn get_lemma(&self) -> PyResult<Option<String>> {
+        match self.sentence()?[self.token_idx] {
+            Node::Token(ref token) => Ok(token.lemma().map(ToOwned::to_owned)),
+            Node::Root => Ok(None),
         }
     }
 
@@ -196,33 +557,43 @@ impl PyToken {
     fn get_misc(&self) -> PyMisc {
         PyMisc {
             sent: self.sent.clone(),
+            generation: self.generation.clone(),
             token_idx: self.token_idx,
         }
     }
 
     /// Get the univeral part-of-speech.
     #[getter]
-    fn get_upos(&self) -> Option<String> {
-        match self.sent.borrow()[self.token_idx] {
-            Node::Token(ref token) => token.upos().map(ToOwned::to_owned),
-            Node::Root => None,
+    fn get_upos(&self) -> PyResult<Option<String>> {
+        match self.sentence()?[self.token_idx] {
+            Node::Token(ref token) => Ok(token.upos().map(ToOwned::to_owned)),
+            Node::Root => Ok(None),
         }
     }
 
     /// Get the language-specific part-of-speech.
     #[getter]
-    fn get_xpos(&self) -> Option<String> {
-        match self.sent.borrow()[self.token_idx] {
-            Node::Token(ref token) => token.xpos().map(ToOwned::to_owned),
-            Node::Root => None,
+    fn get_xpos(&self) -> PyResult<Option<String>> {
+        match self.sentence()?[self.token_idx] {
+            Node::Token(ref token) => Ok(token.xpos().map(ToOwned::to_owned)),
+            Node::Root => Ok(None),
         }
     }
 }
 
+impl PyToken {
+    /// Get the sentence of the token.
+    fn sentence(&self) -> PyResult<Ref<Sentence>> {
+        let sent = self.sent.borrow();
+        check_token_handle(&sent, self.token_idx, &self.generation)?;
+        Ok(sent)
+    }
+}
+
 #[pyproto]
 impl PyObjectProtocol for PyToken {
     fn __repr__(&self) -> PyResult<String> {
-        match self.sent.borrow()[self.token_idx] {
+        match self.sentence()?[self.token_idx] {
             Node::Root => Ok("Root".to_string()),
             Node::Token(ref token) => {
                 let mut attrs = Vec::new();
@@ -254,6 +625,7 @@ impl PyObjectProtocol for PyToken {
 #[pyclass(name=Features,unsendable)]
 pub struct PyFeatures {
     sent: Rc<RefCell<Sentence>>,
+    generation: HandleGeneration,
     token_idx: usize,
 }
 
@@ -268,6 +640,7 @@ impl PyFeatures {
 impl PyFeatures {
     fn token(&self) -> PyResult<Ref<Token>> {
         let sent = self.sent.borrow();
+        check_token_handle(&sent, self.token_idx, &self.generation)?;
 
         if sent[self.token_idx].is_root() {
             return Err(exceptions::PyKeyError::new_err(
@@ -282,6 +655,7 @@ impl PyFeatures {
 
     fn token_mut(&mut self) -> PyResult<RefMut<Token>> {
         let sent = self.sent.borrow_mut();
+        check_token_handle(&sent, self.token_idx, &self.generation)?;
 
         if sent[self.token_idx].is_root() {
             return Err(exceptions::PyKeyError::new_err(
@@ -332,7 +706,10 @@ impl PyMappingProtocol for PyFeatures {
 #[pyproto]
 impl PyObjectProtocol for PyFeatures {
     fn __repr__(&self) -> PyResult<String> {
-        let dict_repr = match &self.sent.borrow()[self.token_idx] {
+        let sent = self.sent.borrow();
+        check_token_handle(&sent, self.token_idx, &self.generation)?;
+
+        let dict_repr = match &sent[self.token_idx] {
             Node::Root => String::new(),
             Node::Token(token) => {
                 let fvals = token
@@ -356,6 +733,7 @@ impl PyObjectProtocol for PyFeatures {
 #[pyclass(name=Misc,unsendable)]
 pub struct PyMisc {
     sent: Rc<RefCell<Sentence>>,
+    generation: HandleGeneration,
     token_idx: usize,
 }
 
@@ -370,6 +748,7 @@ impl PyMisc {
 impl PyMisc {
     fn token(&self) -> PyResult<Ref<Token>> {
         let sent = self.sent.borrow();
+        check_token_handle(&sent, self.token_idx, &self.generation)?;
 
         if sent[self.token_idx].is_root() {
             return Err(exceptions::PyKeyError::new_err(
@@ -384,6 +763,7 @@ impl PyMisc {
 
     fn token_mut(&mut self) -> PyResult<RefMut<Token>> {
         let sent = self.sent.borrow_mut();
+        check_token_handle(&sent, self.token_idx, &self.generation)?;
 
         if sent[self.token_idx].is_root() {
             return Err(exceptions::PyKeyError::new_err(
@@ -437,7 +817,10 @@ impl PyMappingProtocol for PyMisc {
 #[pyproto]
 impl PyObjectProtocol for PyMisc {
     fn __repr__(&self) -> PyResult<String> {
-        let dict_repr = match &self.sent.borrow()[self.token_idx] {
+        let sent = self.sent.borrow();
+        check_token_handle(&sent, self.token_idx, &self.generation)?;
+
+        let dict_repr = match &sent[self.token_idx] {
             Node::Root => String::new(),
             Node::Token(token) => {
                 let fvals = token
@@ -458,3 +841,162 @@ impl PyObjectProtocol for PyMisc {
         self.__repr__()
     }
 }
+
+/// Generation of a token handle.
+///
+/// Edits that renumber the tokens of a sentence increase the generation
+/// of the sentence. A handle is stale when the generation of its
+/// sentence differs from the generation in which the handle was
+/// created, since its index may refer to another token.
+#[derive(Clone)]
+struct HandleGeneration {
+    sentence: Rc<Cell<usize>>,
+    handle: usize,
+}
+
+impl HandleGeneration {
+    fn new(sentence: &Rc<Cell<usize>>) -> Self {
+        HandleGeneration {
+            sentence: sentence.clone(),
+            handle: sentence.get(),
+        }
+    }
+
+    fn is_stale(&self) -> bool {
+        self.sentence.get() != self.handle
+    }
+}
+
+/// Check that a token handle refers to a node of its sentence.
+///
+/// Handles are invalidated when tokens are inserted, deleted, merged,
+/// or split.
+fn check_token_handle(
+    sentence: &Sentence,
+    token_idx: usize,
+    generation: &HandleGeneration,
+) -> PyResult<()> {
+    if generation.is_stale() {
+        Err(exceptions::PyIndexError::new_err(format!(
+            "token {} was invalidated by an edit of the sentence",
+            token_idx
+        )))
+    } else if token_idx < sentence.len() {
+        Ok(())
+    } else {
+        Err(exceptions::PyIndexError::new_err(format!(
+            "token {} is no longer in the sentence",
+            token_idx
+        )))
+    }
+}
+
+/// Get the features of a token in CoNLL-U format.
+///
+/// Returns `None` if the token does not have features.
+pub(crate) fn features_to_string(token: &Token) -> Option<String> {
+    if token.features().is_empty() {
+        return None;
+    }
+
+    Some(
+        token
+            .features()
+            .iter()
+            .map(|(f, v)| format!("{}={}", f, v))
+            .collect::<Vec<_>>()
+            .join("|"),
+    )
+}
+
+/// Add features in CoNLL-U format to a token.
+pub(crate) fn parse_features(token: &mut Token, features: &str) -> PyResult<()> {
+    if features.is_empty() || features == "_" {
+        return Ok(());
+    }
+
+    for feature in features.split('|') {
+        let mut parts = feature.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) => {
+                token
+                    .features_mut()
+                    .insert(name.to_owned(), value.to_owned());
+            }
+            _ => {
+                return Err(exceptions::PyValueError::new_err(format!(
+                    "feature is not a name-value pair: {}",
+                    feature
+                )))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the misc features of a token in CoNLL-U format.
+///
+/// Returns `None` if the token does not have misc features.
+pub(crate) fn misc_to_string(token: &Token) -> Option<String> {
+    if token.misc().is_empty() {
+        return None;
+    }
+
+    Some(
+        token
+            .misc()
+            .iter()
+            .map(|(f, v)| match v {
+                Some(v) => format!("{}={}", f, v),
+                None => f.to_owned(),
+            })
+            .collect::<Vec<_>>()
+            .join("|"),
+    )
+}
+
+/// Add misc features in CoNLL-U format to a token.
+pub(crate) fn parse_misc(token: &mut Token, misc: &str) {
+    if misc.is_empty() || misc == "_" {
+        return;
+    }
+
+    for feature in misc.split('|') {
+        let mut parts = feature.splitn(2, '=');
+        let name = parts.next().unwrap_or_default();
+        token
+            .misc_mut()
+            .insert(name.to_owned(), parts.next().map(ToOwned::to_owned));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PySentence;
+
+    #[test]
+    fn insert_invalidates_token_handles() {
+        let sentence = PySentence::__new__(vec!["Hallo", "Welt"]);
+        let token = sentence.token(2);
+        let features = token.get_features();
+        assert_eq!(token.get_form().unwrap(), Some("Welt".to_owned()));
+
+        sentence.insert(1, "Hey").unwrap();
+        assert!(token.get_form().is_err());
+        assert!(features.token().is_err());
+        assert_eq!(
+            sentence.token(3).get_form().unwrap(),
+            Some("Welt".to_owned())
+        );
+    }
+
+    #[test]
+    fn failed_edit_keeps_token_handles() {
+        let sentence = PySentence::__new__(vec!["Hallo", "Welt"]);
+        let token = sentence.token(2);
+
+        assert!(sentence.delete(5).is_err());
+        assert_eq!(token.get_form().unwrap(), Some("Welt".to_owned()));
+    }
+}
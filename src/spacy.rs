@@ -0,0 +1,237 @@
+//! Conversion between sentences and spaCy documents.
+//!
+//! spaCy is not a dependency of this module, all spaCy objects are
+//! accessed dynamically. This requires spaCy 3.
+
+use conllu::graph::Sentence;
+use conllu::token::Token;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::edit::build;
+use crate::sentence::{features_to_string, parse_features};
+use crate::PyAnnotator;
+
+/// Convert a sentence to a spaCy `Doc`.
+pub fn to_spacy(py: Python, sentence: &Sentence, vocab: &PyAny) -> PyResult<PyObject> {
+    let dep_graph = sentence.dep_graph();
+
+    let mut words = Vec::with_capacity(sentence.len() - 1);
+    let mut spaces = Vec::with_capacity(sentence.len() - 1);
+    let mut lemmas = Vec::with_capacity(sentence.len() - 1);
+    let mut pos = Vec::with_capacity(sentence.len() - 1);
+    let mut tags = Vec::with_capacity(sentence.len() - 1);
+    let mut morphs = Vec::with_capacity(sentence.len() - 1);
+    let mut heads = Vec::with_capacity(sentence.len() - 1);
+    let mut deps = Vec::with_capacity(sentence.len() - 1);
+
+    for token_idx in 1..sentence.len() {
+        let token = sentence[token_idx].token().unwrap();
+
+        words.push(token.form().to_owned());
+        spaces.push(space_after(token));
+        lemmas.push(token.lemma().map(ToOwned::to_owned));
+        pos.push(token.upos().map(ToOwned::to_owned));
+        tags.push(token.xpos().map(ToOwned::to_owned));
+        morphs.push(features_to_string(token));
+
+        match dep_graph.head(token_idx) {
+            Some(triple) => {
+                // spaCy attaches the root token to itself.
+                let head = if triple.head() == 0 {
+                    token_idx
+                } else {
+                    triple.head()
+                };
+                heads.push(Some(head - 1));
+                deps.push(triple.relation().map(ToOwned::to_owned));
+            }
+            None => {
+                heads.push(None);
+                deps.push(None);
+            }
+        }
+    }
+
+    let kwargs = PyDict::new(py);
+    kwargs.set_item("words", words)?;
+    kwargs.set_item("spaces", spaces)?;
+    kwargs.set_item("lemmas", lemmas)?;
+    kwargs.set_item("pos", pos)?;
+    kwargs.set_item("tags", tags)?;
+    kwargs.set_item("morphs", morphs)?;
+
+    // Only pass dependency relations for parsed sentences, since spaCy
+    // marks a document as parsed when heads are provided.
+    if heads.iter().any(Option::is_some) {
+        kwargs.set_item("heads", heads)?;
+        kwargs.set_item("deps", deps)?;
+    }
+
+    let doc = py
+        .import("spacy.tokens")?
+        .getattr("Doc")?
+        .call((vocab,), Some(kwargs))?;
+
+    Ok(doc.to_object(py))
+}
+
+/// Convert a spaCy `Doc` or `Span` to a sentence.
+///
+/// Tokens of a span that are attached to a head outside the span are
+/// attached to the root.
+pub fn from_spacy(doc_or_span: &PyAny) -> PyResult<Sentence> {
+    // Spans have start offsets, documents start at 0.
+    let (doc, start) = if doc_or_span.hasattr("start")? {
+        (
+            doc_or_span.getattr("doc")?,
+            doc_or_span.getattr("start")?.extract::<usize>()?,
+        )
+    } else {
+        (doc_or_span, 0)
+    };
+    let end = start + doc_or_span.len()?;
+
+    let parsed = doc
+        .call_method1("has_annotation", ("DEP",))?
+        .extract::<bool>()?;
+
+    let mut tokens = Vec::new();
+    let mut arcs = Vec::new();
+
+    for spacy_token in doc_or_span.iter()? {
+        let spacy_token = spacy_token?;
+        let idx = spacy_token.getattr("i")?.extract::<usize>()?;
+
+        let mut token = Token::new(spacy_token.getattr("text")?.extract::<&str>()?);
+        token.set_lemma(non_empty_attr(spacy_token, "lemma_")?);
+        token.set_upos(non_empty_attr(spacy_token, "pos_")?);
+        token.set_xpos(non_empty_attr(spacy_token, "tag_")?);
+        parse_features(&mut token, spacy_token.getattr("morph")?.str()?.to_str()?)?;
+
+        if spacy_token
+            .getattr("whitespace_")?
+            .extract::<&str>()?
+            .is_empty()
+        {
+            token
+                .misc_mut()
+                .insert("SpaceAfter".to_owned(), Some("No".to_owned()));
+        }
+
+        if parsed {
+            let head = spacy_token
+                .getattr("head")?
+                .getattr("i")?
+                .extract::<usize>()?;
+            let head = if head == idx || head < start || head >= end {
+                0
+            } else {
+                head - start + 1
+            };
+
+            arcs.push((head, non_empty_attr(spacy_token, "dep_")?, idx - start + 1));
+        }
+
+        tokens.push(token);
+    }
+
+    Ok(build(tokens, arcs))
+}
+
+/// Register an annotator as a spaCy pipeline component.
+pub fn register_spacy_component(
+    py: Python,
+    annotator: Py<PyAnnotator>,
+    name: &str,
+) -> PyResult<()> {
+    let component = Py::new(py, PySpacyComponent { annotator })?;
+
+    let kwargs = PyDict::new(py);
+    kwargs.set_item("func", component)?;
+
+    py.import("spacy.language")?
+        .getattr("Language")?
+        .call_method("component", (name,), Some(kwargs))?;
+
+    Ok(())
+}
+
+/// spaCy pipeline component that annotates documents.
+///
+/// The annotations are added to the tokens of the document.
+#[pyclass(name=SpacyComponent)]
+pub struct PySpacyComponent {
+    annotator: Py<PyAnnotator>,
+}
+
+#[pymethods]
+impl PySpacyComponent {
+    #[call]
+    fn __call__(&self, py: Python, doc: &PyAny) -> PyResult<PyObject> {
+        let sentence = from_spacy(doc)?;
+
+        let sentence = self
+            .annotator
+            .as_ref(py)
+            .borrow()
+            .tag_sentences(vec![sentence])?
+            .pop()
+            .expect("Tagging returned empty Vec");
+
+        let dep_graph = sentence.dep_graph();
+        for token_idx in 1..sentence.len() {
+            let token = sentence[token_idx].token().unwrap();
+            let spacy_token = doc.get_item(token_idx - 1)?;
+
+            if let Some(lemma) = token.lemma() {
+                spacy_token.setattr("lemma_", lemma)?;
+            }
+
+            if let Some(upos) = token.upos() {
+                spacy_token.setattr("pos_", upos)?;
+            }
+
+            if let Some(xpos) = token.xpos() {
+                spacy_token.setattr("tag_", xpos)?;
+            }
+
+            if let Some(features) = features_to_string(token) {
+                spacy_token.call_method1("set_morph", (features,))?;
+            }
+
+            if let Some(triple) = dep_graph.head(token_idx) {
+                let head = if triple.head() == 0 {
+                    token_idx
+                } else {
+                    triple.head()
+                };
+                spacy_token.setattr("head", doc.get_item(head - 1)?)?;
+
+                if let Some(relation) = triple.relation() {
+                    spacy_token.setattr("dep_", relation)?;
+                }
+            }
+        }
+
+        Ok(doc.to_object(py))
+    }
+}
+
+/// Get a string attribute, mapping empty strings to `None`.
+fn non_empty_attr(obj: &PyAny, attr: &str) -> PyResult<Option<String>> {
+    let value = obj.getattr(attr)?.extract::<String>()?;
+    if value.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(value))
+    }
+}
+
+/// Check whether a token is followed by whitespace.
+fn space_after(token: &Token) -> bool {
+    match token.misc().get("SpaceAfter") {
+        Some(Some(value)) => value != "No",
+        _ => true,
+    }
+}
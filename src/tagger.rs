@@ -0,0 +1,347 @@
+//! Sequence tagging.
+//!
+//! This tagger is a variant of `sticker2::tagger::Tagger` that decodes
+//! labels from the full label distributions of the model. This makes
+//! it possible to restrict the labels of a token to the labels that a
+//! lexicon admits, choosing the most probable admissible label.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+use conllu::graph::{Node, Sentence};
+use conllu::token::Token;
+use ndarray::{Array1, Array2, ArrayD, ArrayView1, Axis, Ix3};
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use sticker2::encoders::{Encoder, Encoders};
+use sticker2::input::SentenceWithPieces;
+use sticker2::model::bert::{BertModel, FreezeLayers};
+use sticker2::tensor::{NoLabels, TensorBuilder, Tensors};
+use sticker2::util::seq_len_to_mask;
+use sticker_encoders::{EncodingProb, SentenceDecoder, SentenceEncoder};
+use tch::{Device, Kind};
+
+use crate::layer::Layer;
+use crate::lexicon::Lexicon;
+
+/// Number of labels that are passed to a decoder per token.
+const TOP_K: usize = 3;
+
+/// Number of reserved labels (padding and continuation pieces).
+const N_RESERVED_LABELS: usize = 2;
+
+/// Label distributions of the tokens of a sentence, per encoder.
+///
+/// Every distribution has the shape `[n_tokens, n_labels]`.
+pub type Distributions = HashMap<String, Array2<f32>>;
+
+/// A sequence tagger.
+pub struct Tagger {
+    device: Device,
+    encoders: Encoders,
+    model: BertModel,
+}
+
+impl Tagger {
+    /// Construct a new tagger.
+    ///
+    /// The tagger uses read-only copies of the encoders, see
+    /// `read_only_encoders`.
+    pub fn new(device: Device, model: BertModel, encoders: &Encoders) -> PyResult<Self> {
+        Ok(Tagger {
+            device,
+            encoders: read_only_encoders(encoders)?,
+            model,
+        })
+    }
+
+    /// Tag sentences.
+    ///
+    /// The labels of the layers that have a lexicon are restricted to
+    /// the labels that the lexicon admits. Returns the label
+    /// distributions of the sentences.
+    pub fn tag_sentences(
+        &self,
+        sentences: &mut [SentenceWithPieces],
+        lexicons: &[Lexicon],
+    ) -> PyResult<Vec<Distributions>> {
+        let distributions = self
+            .distributions(sentences)
+            .map_err(|err| exceptions::PyRuntimeError::new_err(err.to_string()))?;
+
+        for (sentence, distributions) in sentences.iter_mut().zip(&distributions) {
+            let sentence = &mut sentence.sentence;
+            let forms = sentence
+                .iter()
+                .filter_map(Node::token)
+                .map(|token| token.form().to_owned())
+                .collect::<Vec<_>>();
+
+            for encoder in &*self.encoders {
+                let probs = &distributions[encoder.name()];
+                let lexicon = lexicons
+                    .iter()
+                    .find(|lexicon| lexicon.layer().name() == encoder.name());
+
+                // Labels of tokens for which the model does not know any
+                // admissible label, these are set after decoding.
+                let mut fallbacks = Vec::new();
+
+                let mut labels = Vec::with_capacity(forms.len());
+                for (token_idx, (form, token_probs)) in
+                    forms.iter().zip(probs.outer_iter()).enumerate()
+                {
+                    let admissible = lexicon
+                        .and_then(|lexicon| lexicon.labels(form).map(|labels| (lexicon, labels)));
+
+                    let token_labels = match admissible {
+                        Some((lexicon, admissible)) => {
+                            let ids = label_ids(
+                                encoder.encoder(),
+                                lexicon.layer(),
+                                form,
+                                admissible,
+                                token_probs.len(),
+                            )?;
+
+                            if ids.is_empty() {
+                                fallbacks.push((token_idx + 1, &admissible[0]));
+                                top_k(token_probs, TOP_K)
+                            } else {
+                                rank(token_probs, ids)
+                            }
+                        }
+                        None => top_k(token_probs, TOP_K),
+                    };
+
+                    labels.push(token_labels);
+                }
+
+                encoder
+                    .encoder()
+                    .decode(&labels, sentence)
+                    .map_err(|err| exceptions::PyRuntimeError::new_err(err.to_string()))?;
+
+                if let Some(lexicon) = lexicon {
+                    for (token_idx, label) in fallbacks {
+                        let token = sentence[token_idx].token_mut().unwrap();
+                        lexicon.layer().set_label(token, label)?;
+                    }
+                }
+            }
+        }
+
+        Ok(distributions)
+    }
+
+    /// Compute the label distributions of the tokens of sentences.
+    fn distributions(
+        &self,
+        sentences: &[SentenceWithPieces],
+    ) -> Result<Vec<Distributions>, anyhow::Error> {
+        let tensors = self.prepare_batch(sentences);
+        let mask = seq_len_to_mask(&tensors.seq_lens, tensors.inputs.size()[1]);
+
+        let logits = tch::no_grad(|| {
+            self.model.logits(
+                &tensors.inputs.to_device(self.device),
+                &mask.to_device(self.device),
+                false,
+                FreezeLayers {
+                    embeddings: true,
+                    encoder: true,
+                    classifiers: true,
+                },
+            )
+        });
+
+        // Distributions of shape [batch_size, seq_len, n_labels].
+        let mut batch_probs = HashMap::new();
+        for (encoder_name, logits) in logits {
+            let probs: ArrayD<f32> = (&logits.softmax(-1, Kind::Float)).try_into()?;
+            batch_probs.insert(encoder_name, probs.into_dimensionality::<Ix3>()?);
+        }
+
+        Ok(sentences
+            .iter()
+            .enumerate()
+            .map(|(idx, sentence)| {
+                batch_probs
+                    .iter()
+                    .map(|(encoder_name, probs)| {
+                        (
+                            encoder_name.clone(),
+                            probs
+                                .index_axis(Axis(0), idx)
+                                .select(Axis(0), &sentence.token_offsets),
+                        )
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Construct the tensor representations of a batch of sentences.
+    fn prepare_batch(&self, sentences: &[SentenceWithPieces]) -> Tensors {
+        let max_seq_len = sentences
+            .iter()
+            .map(|sentence| sentence.pieces.len())
+            .max()
+            .unwrap_or(0);
+
+        let mut builder: TensorBuilder<NoLabels> = TensorBuilder::new(
+            sentences.len(),
+            max_seq_len,
+            self.encoders.iter().map(|encoder| encoder.name()),
+        );
+
+        for sentence in sentences {
+            let input = sentence.pieces.view();
+            let mut token_mask = Array1::zeros((input.len(),));
+            for &token_idx in &sentence.token_offsets {
+                token_mask[token_idx] = 1;
+            }
+
+            builder.add_without_labels(input, token_mask.view());
+        }
+
+        builder.into()
+    }
+}
+
+/// Get read-only copies of encoders.
+///
+/// The encoders of a model add unknown labels to their numberers,
+/// which are not thread-safe. Encoders that are deserialized use
+/// immutable numberers instead, which encode unknown labels as the
+/// reserved label `0`. So, encoders are copied by serializing and
+/// deserializing them.
+fn read_only_encoders(encoders: &Encoders) -> PyResult<Encoders> {
+    serde_json::to_value(encoders)
+        .and_then(serde_json::from_value)
+        .map_err(|err| exceptions::PyValueError::new_err(format!("cannot copy encoders: {}", err)))
+}
+
+/// Get the numeric labels of the admissible labels of a form.
+///
+/// Labels that are not known to the model are left out.
+fn label_ids(
+    encoder: &Encoder,
+    layer: &Layer,
+    form: &str,
+    labels: &[String],
+    n_labels: usize,
+) -> PyResult<Vec<usize>> {
+    let mut ids = Vec::with_capacity(labels.len());
+
+    for label in labels {
+        let mut token = Token::new(form);
+        layer.set_label(&mut token, label)?;
+        let sentence = std::iter::once(token).collect::<Sentence>();
+
+        // Unknown labels are encoded as a reserved label, labels that
+        // cannot be encoded at all are left out.
+        let id = match encoder.encode(&sentence) {
+            Ok(encoding) => encoding[0],
+            Err(_) => continue,
+        };
+
+        if id >= N_RESERVED_LABELS && id < n_labels && !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+
+    Ok(ids)
+}
+
+/// Rank labels by their probabilities.
+fn rank(
+    probs: ArrayView1<f32>,
+    labels: impl IntoIterator<Item = usize>,
+) -> Vec<EncodingProb<usize>> {
+    let mut ranked = labels
+        .into_iter()
+        .map(|label| EncodingProb::new(label, probs[label]))
+        .collect::<Vec<_>>();
+
+    ranked.sort_by(|a, b| {
+        b.prob()
+            .partial_cmp(&a.prob())
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a.encoding().cmp(b.encoding()))
+    });
+
+    ranked
+}
+
+/// Get the `k` most probable labels, excluding reserved labels.
+fn top_k(probs: ArrayView1<f32>, k: usize) -> Vec<EncodingProb<usize>> {
+    let mut labels = rank(probs, N_RESERVED_LABELS..probs.len());
+    labels.truncate(k);
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use conllu::graph::Sentence;
+    use conllu::token::{Token, TokenBuilder};
+    use ndarray::arr1;
+    use sticker2::encoders::{Encoders, EncodersConfig};
+    use sticker_encoders::SentenceEncoder;
+
+    use super::{rank, read_only_encoders, top_k};
+
+    const ENCODERS: &str = r#"[
+        {
+            "name": "dep",
+            "encoder": {
+                "dependency": {
+                    "encoder": { "relativepos": "xpos" },
+                    "root_relation": "root"
+                }
+            }
+        },
+        { "name": "pos", "encoder": { "sequence": "xpos" } }
+    ]"#;
+
+    fn tagged(xpos: &str) -> Sentence {
+        std::iter::once(Token::from(TokenBuilder::new("a").xpos(xpos))).collect()
+    }
+
+    fn encodings(probs: &[sticker_encoders::EncodingProb<usize>]) -> Vec<usize> {
+        probs.iter().map(|prob| *prob.encoding()).collect()
+    }
+
+    #[test]
+    fn rank_orders_by_probability() {
+        let probs = arr1(&[0.0, 0.0, 0.1, 0.5, 0.1, 0.3]);
+        assert_eq!(encodings(&rank(probs.view(), vec![2, 4, 5])), vec![5, 2, 4]);
+    }
+
+    #[test]
+    fn top_k_excludes_reserved_labels() {
+        let probs = arr1(&[0.4, 0.3, 0.1, 0.05, 0.15]);
+        assert_eq!(encodings(&top_k(probs.view(), 2)), vec![4, 2]);
+    }
+
+    #[test]
+    fn top_k_includes_last_label() {
+        let probs = arr1(&[0.0, 0.0, 0.2, 0.3, 0.5]);
+        assert_eq!(encodings(&top_k(probs.view(), 3)), vec![4, 3, 2]);
+    }
+
+    #[test]
+    fn read_only_encoders_do_not_add_labels() {
+        let config: EncodersConfig = serde_json::from_str(ENCODERS).unwrap();
+        let encoders = Encoders::from(&config);
+        let pos = &encoders[1];
+        assert_eq!(pos.encoder().encode(&tagged("NN")).unwrap(), vec![2]);
+
+        let read_only = read_only_encoders(&encoders).unwrap();
+        let pos = read_only[1].encoder();
+        assert_eq!(pos.encode(&tagged("NN")).unwrap(), vec![2]);
+        assert_eq!(pos.encode(&tagged("VVFIN")).unwrap(), vec![0]);
+        assert_eq!(pos.len(), 3);
+    }
+}
@@ -0,0 +1,605 @@
+//! Decoding of well-formed dependency trees.
+//!
+//! The model predicts a head for every token independently, which
+//! can result in cycles or multiple roots. The decoders in this module
+//! find the tree with a single root that maximizes the product of the
+//! probabilities that the model assigns to the relations of the tree.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use conllu::graph::{Node, Sentence};
+use ndarray::{Array2, ArrayView1, ArrayView2};
+use pyo3::exceptions;
+use pyo3::prelude::*;
+use serde::Deserialize;
+use sticker2::encoders::{Encoder, Encoders};
+use sticker2::graph::chu_liu_edmonds;
+use sticker_encoders::deprel::POSLayer;
+
+use crate::edit::rebuild;
+
+/// Part-of-speech of the root in relative part-of-speech encodings.
+const ROOT_POS: &str = "ROOT";
+
+/// Labels with a lower probability are not used as head candidates.
+const MIN_PROB: f32 = 1e-6;
+
+/// Score of relations that are not predicted by the model.
+///
+/// This score is lower than the log-probability of any candidate.
+const NO_RELATION_SCORE: f32 = -20.;
+
+/// Tree decoding algorithm.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TreeDecoder {
+    /// Chu-Liu-Edmonds, decodes non-projective trees.
+    Mst,
+
+    /// Eisner, decodes projective trees.
+    Eisner,
+}
+
+impl TreeDecoder {
+    /// Get a decoder by its name.
+    pub fn from_name(name: &str) -> PyResult<Self> {
+        match name {
+            "mst" => Ok(TreeDecoder::Mst),
+            "eisner" => Ok(TreeDecoder::Eisner),
+            _ => Err(exceptions::PyValueError::new_err(format!(
+                "unknown tree decoder '{}', expected one of: mst, eisner",
+                name
+            ))),
+        }
+    }
+
+    /// Decode a well-formed tree from the head candidates of the
+    /// tokens of a sentence.
+    ///
+    /// `candidates` contains the candidates of every token, excluding
+    /// the root. Sentences without candidates are returned unchanged.
+    pub fn decode(self, sentence: &Sentence, candidates: &[Vec<HeadCandidate>]) -> Sentence {
+        if candidates.iter().all(Vec::is_empty) {
+            return sentence.clone();
+        }
+
+        let n = sentence.len();
+        let mut scores = Array2::from_elem((n, n), NO_RELATION_SCORE);
+        let mut relations = HashMap::new();
+        for (dependent, token_candidates) in (1..n).zip(candidates) {
+            for candidate in token_candidates {
+                let score = candidate.prob.ln().max(NO_RELATION_SCORE);
+                if score > scores[(candidate.head, dependent)]
+                    || !relations.contains_key(&(candidate.head, dependent))
+                {
+                    scores[(candidate.head, dependent)] = score;
+                    relations.insert((candidate.head, dependent), candidate.relation.as_str());
+                }
+            }
+        }
+
+        let heads = self.decode_heads(scores);
+
+        let arcs = (1..n)
+            .zip(candidates)
+            .map(|(dependent, token_candidates)| {
+                let head = heads[dependent];
+
+                // Relations that are not predicted get the relation of
+                // the most probable candidate with a head other than the
+                // root.
+                let relation = relations.get(&(head, dependent)).cloned().or_else(|| {
+                    token_candidates
+                        .iter()
+                        .filter(|candidate| candidate.head != 0)
+                        .max_by(|a, b| a.prob.partial_cmp(&b.prob).unwrap_or(Ordering::Equal))
+                        .map(|candidate| candidate.relation.as_str())
+                });
+
+                (head, relation.map(ToOwned::to_owned), dependent)
+            })
+            .collect();
+
+        let tokens = (1..n)
+            .map(|idx| sentence[idx].token().unwrap().clone())
+            .collect();
+
+        rebuild(sentence, tokens, arcs)
+    }
+
+    /// Find the best tree with a single root.
+    ///
+    /// `scores` is indexed by head and dependent. Returns the head of
+    /// every node, the head of the root is 0.
+    fn decode_heads(self, mut scores: Array2<f32>) -> Vec<usize> {
+        let n = scores.nrows();
+
+        // Relations to the root are penalized by a constant that is
+        // larger than the difference between the scores of any two
+        // trees, so that the best tree has exactly one relation to the
+        // root.
+        let root_penalty = -NO_RELATION_SCORE * n as f32 + 1.;
+        for dependent in 1..n {
+            scores[(0, dependent)] -= root_penalty;
+        }
+
+        match self {
+            TreeDecoder::Mst => chu_liu_edmonds(scores.view(), 0)
+                .into_iter()
+                .map(|head| head.unwrap_or(0))
+                .collect(),
+            TreeDecoder::Eisner => eisner(scores.view()),
+        }
+    }
+}
+
+/// Candidate head of a token.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeadCandidate {
+    pub head: usize,
+    pub relation: String,
+    pub prob: f32,
+}
+
+/// Head of a dependency label.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum EncodedHead {
+    /// Position of the head relative to the dependent.
+    Position(isize),
+
+    /// Position of the head relative to the dependent, counted in
+    /// tokens with the part-of-speech `pos`.
+    Pos { pos: String, position: isize },
+}
+
+/// Dependency label.
+#[derive(Debug, Deserialize)]
+struct EncodedLabel {
+    head: EncodedHead,
+    label: String,
+}
+
+/// Serialized dependency encoder.
+///
+/// sticker2 does not expose the labels of its encoders, so they are
+/// read from the serialization of the encoder, which is the format of
+/// the labels file.
+#[derive(Deserialize)]
+enum SerializedEncoder {
+    RelativePOS(SerializedCategorical),
+    RelativePosition(SerializedCategorical),
+}
+
+#[derive(Deserialize)]
+struct SerializedCategorical {
+    inner: SerializedDependencyEncoder,
+    numberer: SerializedNumberer,
+}
+
+#[derive(Deserialize)]
+struct SerializedDependencyEncoder {
+    #[serde(default)]
+    pos_layer: Option<POSLayer>,
+}
+
+#[derive(Deserialize)]
+struct SerializedNumberer {
+    values: Vec<EncodedLabel>,
+    start_at: usize,
+}
+
+/// The labels of the dependency encoder of a model.
+pub struct DependencyLabels {
+    encoder_name: String,
+    labels: Vec<EncodedLabel>,
+    pos_layer: Option<POSLayer>,
+    start_at: usize,
+}
+
+impl DependencyLabels {
+    /// Get the labels of the first dependency encoder.
+    ///
+    /// Returns `None` if the model does not have a dependency encoder.
+    pub fn from_encoders(encoders: &Encoders) -> PyResult<Option<Self>> {
+        let encoder = match encoders.iter().find(|encoder| {
+            matches!(
+                encoder.encoder(),
+                Encoder::RelativePOS(_) | Encoder::RelativePosition(_)
+            )
+        }) {
+            Some(encoder) => encoder,
+            None => return Ok(None),
+        };
+
+        let serialized = serde_json::to_value(encoder.encoder())
+            .and_then(serde_json::from_value::<SerializedEncoder>)
+            .map_err(|err| {
+                exceptions::PyValueError::new_err(format!(
+                    "cannot read labels of dependency encoder '{}': {}",
+                    encoder.name(),
+                    err
+                ))
+            })?;
+
+        let categorical = match serialized {
+            SerializedEncoder::RelativePOS(categorical) => categorical,
+            SerializedEncoder::RelativePosition(categorical) => categorical,
+        };
+
+        Ok(Some(DependencyLabels {
+            encoder_name: encoder.name().to_owned(),
+            labels: categorical.numberer.values,
+            pos_layer: categorical.inner.pos_layer,
+            start_at: categorical.numberer.start_at,
+        }))
+    }
+
+    /// Get the name of the dependency encoder.
+    pub fn encoder_name(&self) -> &str {
+        &self.encoder_name
+    }
+
+    /// Get the head candidates of the tokens of a sentence.
+    ///
+    /// `probs` is the label distribution of the dependency encoder,
+    /// with the shape `[n_tokens, n_labels]`. Part-of-speech encoded
+    /// heads are resolved using the part-of-speech tags of the
+    /// sentence.
+    pub fn candidates(
+        &self,
+        sentence: &Sentence,
+        probs: ArrayView2<f32>,
+    ) -> Vec<Vec<HeadCandidate>> {
+        let pos_table = self.pos_layer.map(|layer| pos_table(sentence, layer));
+
+        (1..sentence.len())
+            .zip(probs.outer_iter())
+            .map(|(dependent, token_probs)| {
+                self.token_candidates(sentence.len(), pos_table.as_ref(), dependent, token_probs)
+            })
+            .collect()
+    }
+
+    fn token_candidates(
+        &self,
+        sentence_len: usize,
+        pos_table: Option<&HashMap<&str, Vec<usize>>>,
+        dependent: usize,
+        probs: ArrayView1<f32>,
+    ) -> Vec<HeadCandidate> {
+        let mut candidates = Vec::new();
+        for (idx, label) in self.labels.iter().enumerate() {
+            let prob = match probs.get(idx + self.start_at) {
+                Some(&prob) if prob >= MIN_PROB => prob,
+                _ => continue,
+            };
+
+            let head = match (&label.head, pos_table) {
+                (EncodedHead::Position(position), _) => {
+                    relative_position_head(sentence_len, dependent, *position)
+                }
+                (EncodedHead::Pos { pos, position }, Some(pos_table)) => pos_table
+                    .get(pos.as_str())
+                    .and_then(|indices| relative_pos_head(indices, dependent, *position)),
+                (EncodedHead::Pos { .. }, None) => None,
+            };
+
+            if let Some(head) = head.filter(|&head| head != dependent) {
+                candidates.push(HeadCandidate {
+                    head,
+                    relation: label.label.clone(),
+                    prob,
+                });
+            }
+        }
+
+        candidates
+    }
+}
+
+/// Get the indices of the tokens per part-of-speech tag.
+fn pos_table(sentence: &Sentence, layer: POSLayer) -> HashMap<&str, Vec<usize>> {
+    let mut table = HashMap::new();
+
+    for (idx, node) in sentence.iter().enumerate() {
+        let pos = match node {
+            Node::Root => ROOT_POS,
+            Node::Token(token) => match layer {
+                POSLayer::UPos => token.upos(),
+                POSLayer::XPos => token.xpos(),
+            }
+            .unwrap_or_default(),
+        };
+
+        if !pos.is_empty() {
+            table.entry(pos).or_insert_with(Vec::new).push(idx);
+        }
+    }
+
+    table
+}
+
+/// Get the head of a relative position encoding.
+fn relative_position_head(sentence_len: usize, dependent: usize, position: isize) -> Option<usize> {
+    let head = dependent as isize + position;
+    if head < 0 || head >= sentence_len as isize {
+        None
+    } else {
+        Some(head as usize)
+    }
+}
+
+/// Get the head of a relative part-of-speech encoding.
+///
+/// `indices` are the indices of the tokens with the part-of-speech
+/// of the head. This follows the decoding in `sticker_encoders`.
+fn relative_pos_head(indices: &[usize], dependent: usize, mut position: isize) -> Option<usize> {
+    let dependent_position = match indices.binary_search(&dependent) {
+        Ok(idx) => idx,
+        Err(idx) => {
+            // The insertion point is the first token after the
+            // dependent, which is at relative position 1.
+            if position > 0 {
+                position -= 1;
+            }
+            idx
+        }
+    };
+
+    let head_position = dependent_position as isize + position;
+    if head_position < 0 || head_position >= indices.len() as isize {
+        None
+    } else {
+        Some(indices[head_position as usize])
+    }
+}
+
+/// Find the maximum projective spanning tree rooted at node 0.
+///
+/// Returns the head of every node, the head of the root is 0.
+fn eisner(scores: ArrayView2<f32>) -> Vec<usize> {
+    const LEFT: usize = 0;
+    const RIGHT: usize = 1;
+
+    let n = scores.nrows();
+
+    // Spans are indexed by start, end, and direction. Left spans are
+    // headed by the end, right spans by the start.
+    let mut complete = vec![vec![[0.; 2]; n]; n];
+    let mut complete_split = vec![vec![[0; 2]; n]; n];
+    let mut incomplete = vec![vec![[f32::NEG_INFINITY; 2]; n]; n];
+    let mut incomplete_split = vec![vec![[0; 2]; n]; n];
+
+    for width in 1..n {
+        for start in 0..n - width {
+            let end = start + width;
+
+            let split = argmax(start..end, |split| {
+                complete[start][split][RIGHT] + complete[split + 1][end][LEFT]
+            });
+            let span_score = complete[start][split][RIGHT] + complete[split + 1][end][LEFT];
+            incomplete[start][end][LEFT] = span_score + scores[(end, start)];
+            incomplete[start][end][RIGHT] = span_score + scores[(start, end)];
+            incomplete_split[start][end] = [split; 2];
+
+            let split = argmax(start..end, |split| {
+                complete[start][split][LEFT] + incomplete[split][end][LEFT]
+            });
+            complete[start][end][LEFT] =
+                complete[start][split][LEFT] + incomplete[split][end][LEFT];
+            complete_split[start][end][LEFT] = split;
+
+            let split = argmax(start + 1..end + 1, |split| {
+                incomplete[start][split][RIGHT] + complete[split][end][RIGHT]
+            });
+            complete[start][end][RIGHT] =
+                incomplete[start][split][RIGHT] + complete[split][end][RIGHT];
+            complete_split[start][end][RIGHT] = split;
+        }
+    }
+
+    let mut heads = vec![0; n];
+    let mut agenda = vec![(0, n - 1, RIGHT, true)];
+    while let Some((start, end, direction, is_complete)) = agenda.pop() {
+        if start == end {
+            continue;
+        }
+
+        if is_complete {
+            let split = complete_split[start][end][direction];
+            if direction == LEFT {
+                agenda.push((start, split, LEFT, true));
+                agenda.push((split, end, LEFT, false));
+            } else {
+                agenda.push((start, split, RIGHT, false));
+                agenda.push((split, end, RIGHT, true));
+            }
+        } else {
+            let split = incomplete_split[start][end][direction];
+            if direction == LEFT {
+                heads[start] = end;
+            } else {
+                heads[end] = start;
+            }
+            agenda.push((start, split, RIGHT, true));
+            agenda.push((split + 1, end, LEFT, true));
+        }
+    }
+
+    heads
+}
+
+/// Find the element with the highest score.
+///
+/// Returns the first element with the highest score in the case of
+/// ties.
+fn argmax(iter: impl Iterator<Item = usize>, score: impl Fn(usize) -> f32) -> usize {
+    iter.fold(None, |best: Option<(usize, f32)>, idx| {
+        let idx_score = score(idx);
+        match best {
+            Some((_, best_score)) if best_score.partial_cmp(&idx_score) != Some(Ordering::Less) => {
+                best
+            }
+            _ => Some((idx, idx_score)),
+        }
+    })
+    .map(|(idx, _)| idx)
+    .expect("Cannot find maximum of empty sequence")
+}
+
+#[cfg(test)]
+mod tests {
+    use conllu::graph::{DepTriple, Sentence};
+    use conllu::token::{Token, TokenBuilder};
+    use ndarray::{arr2, Array2};
+
+    use super::{
+        eisner, relative_pos_head, relative_position_head, HeadCandidate, TreeDecoder,
+        NO_RELATION_SCORE,
+    };
+
+    fn is_projective(heads: &[usize]) -> bool {
+        (1..heads.len()).all(|dependent| {
+            let head = heads[dependent];
+            let (start, end) = (head.min(dependent), head.max(dependent));
+            (start + 1..end).all(|inner| {
+                let mut ancestor = inner;
+                while ancestor != 0 && ancestor != head {
+                    ancestor = heads[ancestor];
+                }
+                ancestor == head
+            })
+        })
+    }
+
+    fn scores_from_heads(heads: &[(usize, usize, f32)], n: usize) -> Array2<f32> {
+        let mut scores = Array2::from_elem((n, n), NO_RELATION_SCORE);
+        for &(head, dependent, prob) in heads {
+            scores[(head, dependent)] = prob.ln();
+        }
+        scores
+    }
+
+    fn sentence(n_tokens: usize) -> Sentence {
+        (0..n_tokens)
+            .map(|idx| Token::new(format!("t{}", idx + 1)))
+            .collect()
+    }
+
+    #[test]
+    fn eisner_finds_projective_tree() {
+        // The best tree is non-projective: 1 <- 3, 2 <- 0, 3 <- 2, 4 <- 2
+        // with the arc 3 -> 1 crossing 0 -> 2.
+        let scores = scores_from_heads(
+            &[
+                (3, 1, 0.9),
+                (2, 1, 0.1),
+                (0, 2, 0.9),
+                (2, 3, 0.9),
+                (2, 4, 0.9),
+            ],
+            5,
+        );
+
+        let heads = TreeDecoder::Eisner.decode_heads(scores.clone());
+        assert!(is_projective(&heads));
+        assert_eq!(heads, vec![0, 2, 0, 2, 2]);
+
+        let heads = TreeDecoder::Mst.decode_heads(scores);
+        assert_eq!(heads, vec![0, 3, 0, 2, 2]);
+    }
+
+    #[test]
+    fn eisner_without_penalties() {
+        let scores = arr2(&[
+            [0., 1., 5., 1.],
+            [0., 0., 0., 0.],
+            [0., 4., 0., 4.],
+            [0., 0., 0., 0.],
+        ]);
+        assert_eq!(eisner(scores.view()), vec![0, 2, 0, 2]);
+    }
+
+    #[test]
+    fn mst_breaks_cycles() {
+        // The most probable heads form the cycle 1 -> 2 -> 3 -> 1.
+        let scores = scores_from_heads(
+            &[
+                (2, 1, 0.8),
+                (0, 1, 0.2),
+                (3, 2, 0.9),
+                (0, 2, 0.1),
+                (1, 3, 0.7),
+                (0, 3, 0.3),
+            ],
+            4,
+        );
+
+        // Breaking the cycle at the least probable relation gives the
+        // best tree.
+        for decoder in &[TreeDecoder::Mst, TreeDecoder::Eisner] {
+            let heads = decoder.decode_heads(scores.clone());
+            assert_eq!(heads, vec![0, 2, 3, 0]);
+        }
+    }
+
+    #[test]
+    fn decoders_attach_one_token_to_root() {
+        let scores = scores_from_heads(&[(0, 1, 0.9), (0, 2, 0.9), (0, 3, 0.9), (1, 2, 0.1)], 4);
+
+        for decoder in &[TreeDecoder::Mst, TreeDecoder::Eisner] {
+            let heads = decoder.decode_heads(scores.clone());
+            assert_eq!(heads[1..].iter().filter(|&&head| head == 0).count(), 1);
+        }
+    }
+
+    #[test]
+    fn decode_uses_candidate_relations() {
+        let candidate = |head, relation: &str, prob| HeadCandidate {
+            head,
+            relation: relation.to_owned(),
+            prob,
+        };
+
+        let candidates = vec![
+            vec![candidate(0, "root", 0.6), candidate(2, "nsubj", 0.4)],
+            vec![candidate(0, "root", 0.9), candidate(1, "obj", 0.1)],
+        ];
+
+        let decoded = TreeDecoder::Mst.decode(&sentence(2), &candidates);
+        let graph = decoded.dep_graph();
+        assert_eq!(graph.head(1), Some(DepTriple::new(2, Some("nsubj"), 1)));
+        assert_eq!(graph.head(2), Some(DepTriple::new(0, Some("root"), 2)));
+    }
+
+    #[test]
+    fn decode_without_candidates() {
+        let token: Token = TokenBuilder::new("a").upos("X").into();
+        let sentence = std::iter::once(token).collect::<Sentence>();
+        assert_eq!(TreeDecoder::Mst.decode(&sentence, &[vec![]]), sentence);
+    }
+
+    #[test]
+    fn relative_position_heads() {
+        assert_eq!(relative_position_head(4, 2, -2), Some(0));
+        assert_eq!(relative_position_head(4, 2, 1), Some(3));
+        assert_eq!(relative_position_head(4, 2, 2), None);
+        assert_eq!(relative_position_head(4, 2, -3), None);
+    }
+
+    #[test]
+    fn relative_pos_heads() {
+        let indices = [1, 4, 6];
+
+        // Dependent with the part-of-speech of the head.
+        assert_eq!(relative_pos_head(&indices, 4, -1), Some(1));
+        assert_eq!(relative_pos_head(&indices, 4, 1), Some(6));
+
+        // Dependent with another part-of-speech.
+        assert_eq!(relative_pos_head(&indices, 5, -1), Some(4));
+        assert_eq!(relative_pos_head(&indices, 5, 1), Some(6));
+        assert_eq!(relative_pos_head(&indices, 5, 2), None);
+        assert_eq!(relative_pos_head(&indices, 0, -1), None);
+    }
+}